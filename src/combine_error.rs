@@ -0,0 +1,95 @@
+use std::error::Error;
+use std::fmt;
+use std::io;
+
+use crate::conflict_report::MergeConflict;
+use crate::csv_reading::ParseError;
+
+/// Everything that can stop a combine, with the file and position it happened at
+#[derive(Debug)]
+pub enum CombineError {
+    /// The combine was set up with missing or conflicting options
+    Config { message: String },
+    /// An input file does not exist
+    InputNotFound { path: String },
+    /// Reading an input or writing the output failed
+    Io { path: String, source: io::Error },
+    /// Malformed CSV syntax, e.g. a quoted field that is never closed
+    Parse(ParseError),
+    /// A file's header or rows do not fit the combined schema, e.g. a missing header or a ragged row
+    Schema { path: String, line: usize, message: String },
+    /// The key columns cannot be used to match rows
+    Key { path: String, line: usize, message: String },
+    /// Requested key columns are missing from input headers.  Lists each input with the key columns it lacks
+    MissingKeys { missing: Vec<(String, Vec<String>)> },
+    /// Merged rows disagreed and `ConflictHandling::Fail` was set.  Holds the key column names and every conflict found
+    Conflict { key_columns: Vec<String>, conflicts: Vec<MergeConflict> },
+}
+
+pub type Result<T> = std::result::Result<T, CombineError>;
+
+impl CombineError {
+    pub fn io(path: &str, source: io::Error) -> Self {
+        CombineError::Io { path: path.to_string(), source }
+    }
+}
+
+impl fmt::Display for CombineError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            CombineError::Config { message } => write!(f, "{}", message),
+            CombineError::InputNotFound { path } => write!(f, "Input file '{}' not found", path),
+            CombineError::Io { path, source } => write!(f, "{}: {}", path, source),
+            CombineError::Parse(error) => write!(f, "{}", error),
+            CombineError::Schema { path, line, message } => write!(f, "{}:{}: {}", path, line, message),
+            CombineError::Key { path, line, message } => write!(f, "{}:{}: {}", path, line, message),
+            CombineError::MissingKeys { missing } => {
+                let inputs: Vec<String> = missing.iter().map(|(path, keys)| format!("{} lacks {}", path, keys.join(", "))).collect();
+                write!(f, "key columns missing from input headers: {}", inputs.join("; "))
+            }
+            CombineError::Conflict { conflicts, .. } => match conflicts.as_slice() {
+                [conflict] => write!(f, "merge conflict: {}", conflict),
+                conflicts => write!(f, "{} merge conflicts, the first is {}", conflicts.len(), conflicts[0]),
+            },
+        }
+    }
+}
+
+impl Error for CombineError {
+    fn source(&self) -> Option<&(dyn Error + 'static)> {
+        match self {
+            CombineError::Io { source, .. } => Some(source),
+            CombineError::Parse(error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<ParseError> for CombineError {
+    fn from(error: ParseError) -> Self {
+        CombineError::Parse(error)
+    }
+}
+
+/// Lets callers that only deal in `io::Result` use `?` on combine results
+impl From<CombineError> for io::Error {
+    fn from(error: CombineError) -> Self {
+        let kind = match &error {
+            CombineError::InputNotFound { .. } => io::ErrorKind::NotFound,
+            CombineError::Io { source, .. } => source.kind(),
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
+
+/// Attaches the file being read or written to a plain I/O error
+pub trait IoResultExt<T> {
+    fn with_path(self, path: &str) -> Result<T>;
+}
+
+impl<T> IoResultExt<T> for io::Result<T> {
+    fn with_path(self, path: &str) -> Result<T> {
+        self.map_err(|source| CombineError::io(path, source))
+    }
+}
@@ -0,0 +1,470 @@
+use crate::combine_error::{CombineError, IoResultExt, Result};
+use crate::conflict_report::{ConflictHandling, ReportFormat};
+use std::fs::{self, File};
+use std::io::{BufRead, Write};
+
+use crate::csv_combining::{write_records, CombinedRecords, CombineSummary, CsvDialect, Delimiter, DuplicateHandling, FileDialect, Input, JoinMode, KeepDuplicate, KeyPresence, RaggedRows, SetOperation, OUTPUT_NAME};
+use crate::csv_diffing::{diff_records, write_diff, SnapshotDiff};
+use crate::csv_reading::Trim;
+use crate::csv_writing::QuoteStyle;
+use crate::fuzzy_matching::FuzzyKeys;
+use crate::key_normalization::KeyNormalization;
+use crate::merge_strategy::MergeStrategy;
+use crate::text_encoding::TextEncoding;
+use encoding_rs::Encoding;
+
+/// Builder for a combine run.  Only inputs and an output are required, everything else has the CLI's defaults
+///
+/// ```no_run
+/// use csv_combiner::{Combiner, DuplicateHandling};
+///
+/// let summary = Combiner::new()
+///     .inputs(["employees1_name.csv", "employees1_salary.csv"])
+///     .output("employees.csv")
+///     .keys(["id"])
+///     .duplicates(DuplicateHandling::Merge)
+///     .run()?;
+/// println!("{} warnings", summary.warnings.len());
+/// # Ok::<(), csv_combiner::CombineError>(())
+/// ```
+#[derive(Debug, Default)]
+pub struct Combiner {
+    inputs: Vec<Input>,
+    output: Option<String>,
+    keys: Option<Vec<String>>,
+    dialect: CsvDialect,
+    empty_value: String,
+    duplicates: DuplicateHandling,
+    ragged_rows: RaggedRows,
+    sort_by: Vec<String>,
+    merge_strategy: MergeStrategy,
+    column_merge_strategies: Vec<(String, MergeStrategy)>,
+    conflicts: ConflictHandling,
+    priorities: Vec<(String, i32)>,
+    keep: KeepDuplicate,
+    key_presence: KeyPresence,
+    key_normalization: KeyNormalization,
+    column_key_normalizations: Vec<(String, KeyNormalization)>,
+    fuzzy_keys: Option<FuzzyKeys>,
+    join: JoinMode,
+    set_operation: SetOperation,
+    whole_row_keys: bool,
+}
+
+impl Combiner {
+    pub fn new() -> Self {
+        Combiner::default()
+    }
+
+    /// Adds one input file
+    pub fn input(mut self, path: impl Into<String>) -> Self {
+        self.inputs.push(Input::Path(path.into()));
+        self
+    }
+
+    /// Adds an input that is not a file, e.g. stdin.  `name` stands in for the file name in errors and warnings
+    pub fn input_reader(mut self, name: impl Into<String>, reader: impl BufRead + Send + 'static) -> Self {
+        self.inputs.push(Input::reader(name, reader));
+        self
+    }
+
+    /// Adds several input files, combined in the order given
+    pub fn inputs<I, S>(mut self, paths: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.inputs.extend(paths.into_iter().map(|path| Input::Path(path.into())));
+        self
+    }
+
+    /// File the combined rows are written to
+    pub fn output(mut self, path: impl Into<String>) -> Self {
+        self.output = Some(path.into());
+        self
+    }
+
+    /// Key columns used to find duplicates.  Defaults to every column of the first input's header
+    pub fn keys<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.keys = Some(columns.into_iter().map(Into::into).collect());
+        self
+    }
+
+    /// Whether the key columns must be in every input or just one.  Only checked when keys are set
+    pub fn key_presence(mut self, key_presence: KeyPresence) -> Self {
+        self.key_presence = key_presence;
+        self
+    }
+
+    /// How key values are normalized before rows are compared, for every key column without its own normalization
+    pub fn normalize_keys(mut self, normalization: KeyNormalization) -> Self {
+        self.key_normalization = normalization;
+        self
+    }
+
+    /// How one key column's values are normalized before rows are compared
+    pub fn normalize_key(mut self, column: impl Into<String>, normalization: KeyNormalization) -> Self {
+        self.column_key_normalizations.push((column.into(), normalization));
+        self
+    }
+
+    /// Lets close but unequal keys count as duplicates, see `CombinedRecords::fuzzy_keys`
+    pub fn fuzzy_keys(mut self, fuzzy_keys: FuzzyKeys) -> Self {
+        self.fuzzy_keys = Some(fuzzy_keys);
+        self
+    }
+
+    /// Replaces all dialect settings at once
+    pub fn dialect(mut self, dialect: CsvDialect) -> Self {
+        self.dialect = dialect;
+        self
+    }
+
+    /// Delimiter of the inputs, and of the output unless `output_delimiter` is given
+    pub fn delimiter(mut self, delimiter: char) -> Self {
+        self.dialect.delimiter = delimiter;
+        self
+    }
+
+    /// Delimiter of the output, to convert from the inputs' delimiter
+    pub fn output_delimiter(mut self, delimiter: char) -> Self {
+        self.dialect.output_delimiter = Some(delimiter);
+        self
+    }
+
+    /// Quote character of the inputs
+    pub fn quote(mut self, quote: char) -> Self {
+        self.dialect.quote = quote;
+        self
+    }
+
+    /// Quote character of the output
+    pub fn output_quote(mut self, quote: char) -> Self {
+        self.dialect.output_quote = quote;
+        self
+    }
+
+    /// Detect each input's delimiter, quote character and header row from its first lines
+    pub fn sniff(mut self, sniff: bool) -> Self {
+        self.dialect.sniff = sniff;
+        self
+    }
+
+    /// Delimiter of one input, by the name it was added under
+    pub fn file_delimiter(self, input: impl Into<String>, delimiter: Delimiter) -> Self {
+        self.file_dialect(input, delimiter.into())
+    }
+
+    /// Delimiter, quote character and header row of one input, by the name it was added under
+    pub fn file_dialect(mut self, input: impl Into<String>, dialect: FileDialect) -> Self {
+        self.dialect.file_dialects.push((input.into(), dialect));
+        self
+    }
+
+    /// Character encoding of the inputs, detected by default
+    pub fn encoding(mut self, encoding: TextEncoding) -> Self {
+        self.dialect.encoding = encoding;
+        self
+    }
+
+    /// Encoding of one input, by the name it was added under
+    pub fn file_encoding(mut self, input: impl Into<String>, encoding: TextEncoding) -> Self {
+        self.dialect.file_encodings.push((input.into(), encoding));
+        self
+    }
+
+    /// Character encoding of the output, UTF-8 by default.  It has to be ASCII-compatible, e.g. `encoding_rs::WINDOWS_1252`
+    pub fn output_encoding(mut self, encoding: &'static Encoding) -> Self {
+        self.dialect.output_encoding = encoding;
+        self
+    }
+
+    pub fn quote_style(mut self, quote_style: QuoteStyle) -> Self {
+        self.dialect.quote_style = quote_style;
+        self
+    }
+
+    pub fn trim(mut self, trim: Trim) -> Self {
+        self.dialect.trim = trim;
+        self
+    }
+
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.dialect.lenient = lenient;
+        self
+    }
+
+    /// Value written for columns a row does not have
+    pub fn empty_value(mut self, empty_value: impl Into<String>) -> Self {
+        self.empty_value = empty_value.into();
+        self
+    }
+
+    pub fn duplicates(mut self, duplicates: DuplicateHandling) -> Self {
+        self.duplicates = duplicates;
+        self
+    }
+
+    pub fn ragged_rows(mut self, ragged_rows: RaggedRows) -> Self {
+        self.ragged_rows = ragged_rows;
+        self
+    }
+
+    /// How merged duplicates combine their values, for every column without its own strategy
+    pub fn merge_strategy(mut self, strategy: MergeStrategy) -> Self {
+        self.merge_strategy = strategy;
+        self
+    }
+
+    /// How merged duplicates combine the values of one column
+    pub fn column_merge_strategy(mut self, column: impl Into<String>, strategy: MergeStrategy) -> Self {
+        self.column_merge_strategies.push((column.into(), strategy));
+        self
+    }
+
+    /// Which merged rows are written, by the inputs their key appears in
+    pub fn join(mut self, join: JoinMode) -> Self {
+        self.join = join;
+        self
+    }
+
+    /// Which distinct rows are written when removing duplicates, by the inputs their key appears in
+    pub fn set_operation(mut self, set_operation: SetOperation) -> Self {
+        self.set_operation = set_operation;
+        self
+    }
+
+    /// Compare whole rows instead of the key columns when looking for duplicates
+    pub fn whole_row_keys(mut self, whole_row_keys: bool) -> Self {
+        self.whole_row_keys = whole_row_keys;
+        self
+    }
+
+    /// Which row survives when duplicates are removed
+    pub fn keep(mut self, keep: KeepDuplicate) -> Self {
+        self.keep = keep;
+        self
+    }
+
+    /// Ranks an input above or below the others when removing or merging duplicates, see `CombinedRecords::priority`
+    pub fn priority(mut self, input: impl Into<String>, priority: i32) -> Self {
+        self.priorities.push((input.into(), priority));
+        self
+    }
+
+    /// Whether merged rows that disagree are reported in the summary or stop the run
+    pub fn conflicts(mut self, conflicts: ConflictHandling) -> Self {
+        self.conflicts = conflicts;
+        self
+    }
+
+    /// Columns to sort the output by, see `CombinedRecords::sort_by`.  Unsorted output keeps the input order
+    pub fn sort_by<I, S>(mut self, columns: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        self.sort_by = columns.into_iter().map(Into::into).collect();
+        self
+    }
+
+    /// Combines the inputs into the output file.  Rows go to a temporary file next to it that only replaces the output
+    /// once everything is written, so a failed run leaves an existing output as it was
+    pub fn run(self) -> Result<CombineSummary> {
+        let Some(output) = self.output.clone() else {
+            return Err(CombineError::Config { message: "an output file is required".to_string() });
+        };
+        let dialect = self.dialect.clone();
+        let records = self.records()?;
+        let temp_output = format!("{}.tmp", output);
+        let result = File::create(&temp_output).with_path(&output).and_then(|file| write_records(records, file, &dialect, &output));
+        match result {
+            Ok(summary) => {
+                fs::rename(&temp_output, &output).with_path(&output)?;
+                Ok(summary)
+            }
+            Err(e) => {
+                let _ = fs::remove_file(&temp_output); //the error is what matters, not whether anything was left to remove
+                Err(e)
+            }
+        }
+    }
+
+    /// Combines the inputs into any writer, e.g. stdout.  The output setting is ignored
+    pub fn run_to<W: Write>(self, writer: W) -> Result<CombineSummary> {
+        let dialect = self.dialect.clone();
+        let records = self.records()?;
+        write_records(records, writer, &dialect, OUTPUT_NAME)
+    }
+
+    /// Compares two inputs, the old snapshot first, by key instead of combining them.  Uses the keys, key presence,
+    /// key normalization, dialect, empty value and ragged row settings; duplicate handling, sorting and merging are ignored
+    pub fn diff(self) -> Result<SnapshotDiff> {
+        if self.inputs.len() != 2 {
+            return Err(CombineError::Config { message: format!("a diff needs exactly two inputs, the old and the new snapshot, got {}", self.inputs.len()) });
+        }
+        let keys: Option<Vec<&str>> = self.keys.as_ref().map(|v| v.iter().map(|s| s.as_str()).collect());
+        let mut records = CombinedRecords::from_inputs(self.inputs, keys.as_deref(), &self.dialect, &self.empty_value, DuplicateHandling::Keep, self.ragged_rows, self.key_presence)?
+            .normalize_keys(self.key_normalization);
+        for (column, normalization) in self.column_key_normalizations {
+            records = records.normalize_key(&column, normalization)?;
+        }
+        diff_records(records)
+    }
+
+    /// Compares two inputs like `diff` and writes the changes to any writer with the output dialect, see `write_diff`.
+    /// The output setting is ignored
+    pub fn diff_to<W: Write>(self, writer: W, format: ReportFormat) -> Result<SnapshotDiff> {
+        let dialect = self.dialect.clone();
+        let diff = self.diff()?;
+        write_diff(&diff, writer, format, &dialect).with_path(OUTPUT_NAME)?;
+        Ok(diff)
+    }
+
+    /// Combined rows as an iterator instead of a file.  The output setting is ignored
+    pub fn records(self) -> Result<CombinedRecords> {
+        if self.inputs.is_empty() {
+            return Err(CombineError::Config { message: "at least one input file is required".to_string() });
+        }
+        let keys: Option<Vec<&str>> = self.keys.as_ref().map(|v| v.iter().map(|s| s.as_str()).collect());
+        let mut records = CombinedRecords::from_inputs(self.inputs, keys.as_deref(), &self.dialect, &self.empty_value, self.duplicates, self.ragged_rows, self.key_presence)?
+            .normalize_keys(self.key_normalization)
+            .merge_strategy(self.merge_strategy)
+            .join(self.join)
+            .set_operation(self.set_operation)
+            .whole_row_keys(self.whole_row_keys)
+            .conflicts(self.conflicts)
+            .keep_duplicate(self.keep)?;
+        for (column, strategy) in self.column_merge_strategies {
+            records = records.column_merge_strategy(&column, strategy)?;
+        }
+        for (column, normalization) in self.column_key_normalizations {
+            records = records.normalize_key(&column, normalization)?;
+        }
+        for (input, priority) in self.priorities {
+            records = records.priority(&input, priority)?;
+        }
+        if let Some(fuzzy_keys) = self.fuzzy_keys {
+            records = records.fuzzy_keys(fuzzy_keys);
+        }
+        if self.sort_by.is_empty() {
+            return Ok(records);
+        }
+        let sort_by: Vec<&str> = self.sort_by.iter().map(|s| s.as_str()).collect();
+        records.sort_by(&sort_by)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_combining::Record;
+    use crate::csv_reading::CsvReader;
+    use std::fs;
+
+    #[test]
+    fn test_combiner_merge() -> Result<()> {
+        let output = "csv_testing_output/test_output_combiner.csv";
+        Combiner::new()
+            .input("csv_samples/employees1_name.csv")
+            .inputs(["csv_samples/employees1_department.csv", "csv_samples/employees1_salary.csv"])
+            .output(output)
+            .keys(["id"])
+            .empty_value("EMPTY")
+            .duplicates(DuplicateHandling::Merge)
+            .run()?;
+
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
+        assert_eq!(output_reader.next_record()?.unwrap(), ["id", "name", "department", "salary"]);
+        let mut row_counter = 0;
+        while let Some(fields) = output_reader.next_record()? {
+            row_counter += 1;
+            assert!(!fields.contains(&"EMPTY".to_string()), "Merged rows should be complete");
+        }
+        assert_eq!(row_counter, 5, "Output should have 5 merged rows");
+
+        // Cleanup
+        let _ = fs::remove_file(output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_combiner_merge_strategies() -> Result<()> {
+        let records = Combiner::new()
+            .inputs(["csv_samples/employees1.csv", "csv_samples/employees1_update.csv"])
+            .keys(["id"])
+            .duplicates(DuplicateHandling::Merge)
+            .merge_strategy(MergeStrategy::PreferNonEmptyLatest)
+            .column_merge_strategy("salary", MergeStrategy::Max)
+            .records()?;
+        let rows: Vec<Record> = records.collect::<Result<_>>()?;
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[1].get("department"), Some("Sales"), "Later non-empty value should win");
+        assert_eq!(rows[1].get("salary"), Some("72000"), "Lower salary should not replace a higher one");
+        assert_eq!(rows[3].get("department"), Some("Sales"), "Empty update should not clear the value");
+        assert_eq!(rows[3].get("salary"), Some("69000"));
+
+        let error = Combiner::new()
+            .input("csv_samples/employees1.csv")
+            .keys(["id"])
+            .column_merge_strategy("id", MergeStrategy::Sum)
+            .records()
+            .err()
+            .expect("Key columns cannot be merged");
+        assert!(matches!(error, CombineError::Config { .. }), "Got {:?}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_combiner_records() -> Result<()> {
+        let mut records = Combiner::new()
+            .inputs(["csv_samples/employees1.csv", "csv_samples/employees4.csv"])
+            .empty_value("EMPTY")
+            .records()?;
+        assert_eq!(records.header(), ["id", "name", "department", "salary", "gender"]);
+        let first = records.next().unwrap()?;
+        assert_eq!(first.get("name"), Some("Alice Johnson"));
+        assert_eq!(first.get("gender"), Some("EMPTY"), "Missing columns should hold the empty value");
+        assert_eq!(first.get("address"), None, "Unknown columns should not be found");
+        let rest: Vec<Record> = records.by_ref().collect::<Result<_>>()?;
+        assert_eq!(rest.len(), 9);
+        assert_eq!(rest[4].get("gender"), Some("M"), "Columns should be aligned by name, not position");
+        assert_eq!(rest[4].get("department"), Some("Engineering"));
+        assert_eq!(records.summary().ragged_rows, 0);
+        Ok(())
+    }
+
+    #[test]
+    fn test_combiner_reader_inputs() -> Result<()> {
+        // Reader inputs are only read once, including when spilling needs a full pass before the data rows
+        let extra = "id,name,department,salary\n50,Kim Yu,Sales,70000,remote\n";
+        let mut buffer: Vec<u8> = Vec::new();
+        Combiner::new()
+            .input("csv_samples/employees1.csv")
+            .input_reader("<memory>", extra.as_bytes())
+            .ragged_rows(RaggedRows::Spill)
+            .run_to(&mut buffer)?;
+        let contents = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines[0], "id,name,department,salary,_extra_1");
+        assert_eq!(lines[6], "50,Kim Yu,Sales,70000,remote");
+
+        let data = "id,name,department,salary\n50,Kim Yu,Sales,70000\n";
+        let rows: Vec<Record> = Combiner::new().input_reader("<memory>", data.as_bytes()).input("csv_samples/employees2.csv").records()?.collect::<Result<_>>()?;
+        assert_eq!(rows.len(), 6, "Reader input should be continued after its header");
+        assert_eq!(rows[0].get("name"), Some("Kim Yu"));
+        Ok(())
+    }
+
+    #[test]
+    fn test_combiner_requires_inputs_and_output() {
+        let error = Combiner::new().output("unused.csv").run().unwrap_err();
+        assert!(matches!(error, CombineError::Config { .. }), "Got {:?}", error);
+        let error = Combiner::new().input("csv_samples/employees1.csv").run().unwrap_err();
+        assert!(matches!(error, CombineError::Config { .. }), "Got {:?}", error);
+    }
+}
@@ -0,0 +1,159 @@
+use std::fmt;
+use std::io::{self, Write};
+
+use clap::ValueEnum;
+
+use crate::csv_writing::{CsvWriter, QuoteStyle};
+
+/// What to do when merged rows have different non-empty values for the same column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum ConflictHandling {
+    /// Resolve them with the merge strategy and move on
+    #[default]
+    Ignore,
+    /// Resolve them and list them in `CombineSummary::conflicts`
+    Report,
+    /// Stop with `CombineError::Conflict` before any merged row is written
+    Fail,
+}
+
+/// One value a merged column was given, and where it came from
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ConflictCandidate {
+    pub value: String,
+    pub path: String,
+    /// 1-based line the row starts on
+    pub line: usize,
+}
+
+/// A merged column whose rows did not agree
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MergeConflict {
+    /// Key column names and values of the merged row
+    pub key: Vec<(String, String)>,
+    pub column: String,
+    /// Every non-empty value, in the order read
+    pub candidates: Vec<ConflictCandidate>,
+}
+
+impl fmt::Display for MergeConflict {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let key: Vec<String> = self.key.iter().map(|(column, value)| format!("{}={}", column, value)).collect();
+        write!(f, "{} column '{}':", key.join(","), self.column)?;
+        for (i, candidate) in self.candidates.iter().enumerate() {
+            let separator = if i == 0 { " " } else { " vs " };
+            write!(f, "{}'{}' ({}:{})", separator, candidate.value, candidate.path, candidate.line)?;
+        }
+        Ok(())
+    }
+}
+
+/// File format of a conflict or fuzzy match report
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum ReportFormat {
+    /// One row per value or matched row, starting with the key columns
+    #[default]
+    Csv,
+    /// An array with one object per conflict or matched row
+    Json,
+}
+
+impl ReportFormat {
+    /// JSON for a `.json` file name, CSV for anything else
+    pub fn from_path(path: &str) -> Self {
+        if path.to_ascii_lowercase().ends_with(".json") { ReportFormat::Json } else { ReportFormat::Csv }
+    }
+}
+
+pub(crate) fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for char in value.chars() {
+        match char {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            '\r' => json.push_str("\\r"),
+            '\t' => json.push_str("\\t"),
+            c if c < ' ' => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+fn write_csv<W: Write>(key_columns: &[String], conflicts: &[MergeConflict], writer: W) -> io::Result<()> {
+    let mut csv_writer = CsvWriter::new(writer, ',', QuoteStyle::Necessary);
+    let mut header = key_columns.to_vec();
+    header.extend(["column", "value", "file", "line"].map(String::from));
+    csv_writer.write_record(&header)?;
+    for conflict in conflicts {
+        for candidate in &conflict.candidates {
+            let mut row: Vec<String> = conflict.key.iter().map(|(_, value)| value.clone()).collect();
+            row.extend([conflict.column.clone(), candidate.value.clone(), candidate.path.clone(), candidate.line.to_string()]);
+            csv_writer.write_record(&row)?;
+        }
+    }
+    csv_writer.flush()
+}
+
+fn write_json<W: Write>(conflicts: &[MergeConflict], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "[")?;
+    for (i, conflict) in conflicts.iter().enumerate() {
+        let key: Vec<String> = conflict.key.iter().map(|(column, value)| format!("{}: {}", json_string(column), json_string(value))).collect();
+        let candidates: Vec<String> = conflict.candidates.iter()
+            .map(|candidate| format!("{{\"value\": {}, \"file\": {}, \"line\": {}}}", json_string(&candidate.value), json_string(&candidate.path), candidate.line))
+            .collect();
+        let comma = if i + 1 < conflicts.len() { "," } else { "" };
+        writeln!(writer, "  {{\"key\": {{{}}}, \"column\": {}, \"candidates\": [{}]}}{}", key.join(", "), json_string(&conflict.column), candidates.join(", "), comma)?;
+    }
+    writeln!(writer, "]")?;
+    writer.flush()
+}
+
+/// Writes the conflicts found by a merge for review.  A CSV report starts with `key_columns`, even without conflicts
+pub fn write_conflict_report<W: Write>(key_columns: &[String], conflicts: &[MergeConflict], writer: W, format: ReportFormat) -> io::Result<()> {
+    match format {
+        ReportFormat::Csv => write_csv(key_columns, conflicts, writer),
+        ReportFormat::Json => write_json(conflicts, writer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key_columns() -> Vec<String> {
+        vec!["id".to_string()]
+    }
+
+    fn conflict() -> MergeConflict {
+        let candidate = |value: &str, path: &str, line| ConflictCandidate { value: value.to_string(), path: path.to_string(), line };
+        MergeConflict {
+            key: vec![("id".to_string(), "2".to_string())],
+            column: "notes".to_string(),
+            candidates: vec![candidate("say \"hi\"", "a.csv", 3), candidate("two, words", "b.csv", 7)],
+        }
+    }
+
+    #[test]
+    fn test_report_formats() -> io::Result<()> {
+        let mut buffer: Vec<u8> = Vec::new();
+        write_conflict_report(&key_columns(), &[conflict()], &mut buffer, ReportFormat::Csv)?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "id,column,value,file,line\n2,notes,\"say \"\"hi\"\"\",a.csv,3\n2,notes,\"two, words\",b.csv,7\n");
+        let mut buffer: Vec<u8> = Vec::new();
+        write_conflict_report(&key_columns(), &[], &mut buffer, ReportFormat::Csv)?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "id,column,value,file,line\n", "A report without conflicts should still have the key columns");
+
+        let mut buffer: Vec<u8> = Vec::new();
+        write_conflict_report(&key_columns(), &[conflict()], &mut buffer, ReportFormat::Json)?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), concat!(
+            "[\n",
+            "  {\"key\": {\"id\": \"2\"}, \"column\": \"notes\", \"candidates\": [{\"value\": \"say \\\"hi\\\"\", \"file\": \"a.csv\", \"line\": 3}, {\"value\": \"two, words\", \"file\": \"b.csv\", \"line\": 7}]}\n",
+            "]\n",
+        ));
+        assert_eq!(conflict().to_string(), "id=2 column 'notes': 'say \"hi\"' (a.csv:3) vs 'two, words' (b.csv:7)");
+        Ok(())
+    }
+}
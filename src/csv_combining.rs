@@ -1,163 +1,1160 @@
-use std::io::Result;
+use std::fmt;
 use std::fs::File;
-use std::io::{BufRead, Write, BufReader, BufWriter,Lines};
+use std::io::{BufRead, BufWriter, Cursor, Write};
 use std::collections::{HashSet, HashMap};
 use std::mem;
-// use std::cmp::max;
-
-
-// Define line ending based on OS https://stackoverflow.com/questions/47541191/how-to-get-current-platform-end-of-line-character-sequence-in-rust
-#[cfg(windows)]
-const LINE_ENDING: &'static str = "\r\n";
-#[cfg(not(windows))]
-const LINE_ENDING: &'static str = "\n";
-
-fn parse_line(line: &str, delimiter: char,in_quotes: bool) -> (Vec<String>,bool) {
-    //parses a single line into fields, returning whether we are still in quotes at end of line
-    let line = line.trim();
-    let mut fields: Vec<String> = Vec::new();
-    let mut current_field = String::new();
-    let mut in_quotes = in_quotes;
-    for char in line.chars(){
-        if char == delimiter && !in_quotes {
-            fields.push(current_field);
-            current_field = String::new();
-        } else if char == '"' {
-            current_field.push(char); // keeps the quote as this is going into the final file as-is.
-            in_quotes = !in_quotes;
-        } else {
-            current_field.push(char);
+use std::str::FromStr;
+use std::sync::Arc;
+use std::cmp::{max, Ordering};
+use std::vec;
+use clap::ValueEnum;
+use crate::combine_error::{CombineError, IoResultExt, Result};
+use crate::csv_reading::{open_file, CsvReader, Trim};
+use crate::dialect_sniffing::sniff_dialect;
+use crate::csv_writing::{CsvWriter, QuoteStyle};
+use crate::key_normalization::KeyNormalization;
+use crate::conflict_report::{ConflictCandidate, ConflictHandling, MergeConflict};
+use crate::fuzzy_matching::{FuzzyKeys, FuzzyMatch};
+use crate::merge_strategy::{self, MergeStrategy};
+use crate::text_encoding::{decode_input, EncodingWriter, TextEncoding};
+use encoding_rs::{Encoding, UTF_8};
+
+
+/// Stands in for the file name in errors when writing to a sink that has none
+pub(crate) const OUTPUT_NAME: &str = "<output>";
+
+/// How input files are read and the output file is written
+#[derive(Debug, Clone)]
+pub struct CsvDialect {
+    /// Field delimiter of every input without its own, and of the output unless `output_delimiter` is set
+    pub delimiter: char,
+    /// Quote character of the inputs
+    pub quote: char,
+    /// Field delimiter of the output, when it differs from the inputs
+    pub output_delimiter: Option<char>,
+    /// Quote character of the output
+    pub output_quote: char,
+    /// Whether inputs start with a header row.  Without one, columns are named column_1, column_2, ... by position
+    pub has_header: bool,
+    /// Detect each input's delimiter, quote character and header row from its first lines instead, see `sniff_dialect`
+    pub sniff: bool,
+    /// Delimiters, quote characters and header rows of single inputs by name, overriding the settings above
+    pub file_dialects: Vec<(String, FileDialect)>,
+    /// Character encoding of every input without its own.  Inputs are turned into UTF-8 before they are read
+    pub encoding: TextEncoding,
+    /// Encodings for single inputs by name, overriding `encoding`
+    pub file_encodings: Vec<(String, TextEncoding)>,
+    /// Character encoding of the output, which has to be ASCII-compatible
+    pub output_encoding: &'static Encoding,
+    /// When output fields are quoted
+    pub quote_style: QuoteStyle,
+    /// Which input fields are trimmed
+    pub trim: Trim,
+    /// Close unterminated quoted fields at end of file with a warning instead of failing
+    pub lenient: bool,
+}
+
+impl Default for CsvDialect {
+    fn default() -> Self {
+        CsvDialect {
+            delimiter: ',',
+            quote: '"',
+            output_delimiter: None,
+            output_quote: '"',
+            has_header: true,
+            sniff: false,
+            file_dialects: Vec::new(),
+            encoding: TextEncoding::Auto,
+            file_encodings: Vec::new(),
+            output_encoding: UTF_8,
+            quote_style: QuoteStyle::default(),
+            trim: Trim::default(),
+            lenient: false,
         }
     }
-    fields.push(current_field);
-    return (fields, in_quotes);//returns wheter we are still in quotes at end of line.  Concatenation is left to the caller
 }
 
-fn parse_next_line(lines: &mut Lines<BufReader<File>>, delimiter: char) -> Result<Option<Vec<String>>> {
-    //helper function between parse_line which takes the lines iterator so that it can read multiple lines if needed to parse out multiline fields
-    let line: String = match lines.next() {
-        None => return Ok(None),
-        Some(result) => result?,
-    };
-    let (mut fields, mut in_quotes) = parse_line(&line, delimiter, false);
-    while in_quotes {//read next line, then combine last and first fields.  if still in quotes, repeat
-        let next_line = lines.next().unwrap()?;
-        let (next_fields, still_in_quotes) = parse_line(&next_line, delimiter, in_quotes);
-        let mut last_field = fields.pop().unwrap();
-        last_field.push_str(LINE_ENDING);
-        last_field.push_str(&next_fields[0]);
-        fields.push(last_field);
-        fields.extend_from_slice(&next_fields[1..]);
-        in_quotes = still_in_quotes;
-    }
-    // first_lines is still in scope here if we need to read more lines for multiline fields
-    Ok(Some(fields))
-}
-
-pub fn combine_files_by_keys(filenames: &[&str], output_filename: &str, key_columns: Option<&[&str]>,delimiter: char,empty_field_value: &str,remove_duplicates: bool , merge_duplicates: bool) -> Result<()> {
-    // Determine key columns: either from parameter or from first header
-
-    //process first file to get ideas.
-    
-    
-    
-    let key_columns: Vec<String> = match key_columns {
-        Some(cols) => cols.iter().map(|s| s.to_string()).collect(),
-        None => {
-            //derive from first file
-            let first_file = File::open(filenames[0])?;
-            let first_reader = BufReader::new(first_file);
-            let mut first_lines = first_reader.lines();
-            parse_next_line(&mut first_lines, delimiter)?.unwrap()
+impl CsvDialect {
+    /// Checks that every delimiter can be told apart from its quote character and from line breaks, and that the
+    /// output encoding can be written
+    pub fn validate(&self) -> Result<()> {
+        if !self.output_encoding.is_ascii_compatible() {
+            return Err(CombineError::Config { message: format!("{} cannot be written, the output encoding must be ASCII-compatible", self.output_encoding.name()) });
+        }
+        check_delimiter(self.delimiter, self.quote, "")?;
+        for (_, file_dialect) in &self.file_dialects {
+            if let Delimiter::Char(delimiter) = file_dialect.delimiter {
+                check_delimiter(delimiter, file_dialect.quote.unwrap_or(self.quote), "")?;
+            }
+        }
+        check_delimiter(self.output_delimiter(), self.output_quote, "output ")
+    }
+
+    /// Field delimiter the output is written with
+    pub fn output_delimiter(&self) -> char {
+        self.output_delimiter.unwrap_or(self.delimiter)
+    }
+
+    /// Turns an input into UTF-8 and works out its dialect.  The dialect returned names the encoding found, so the
+    /// input can be opened again without detecting it, unless nothing but ASCII was buffered (see `decode_input`)
+    fn open_input(&self, name: &str, reader: impl BufRead + Send + 'static) -> Result<(Box<dyn BufRead + Send>, CsvDialect)> {
+        let encoding = self.file_encodings.iter().rev().find(|(input, _)| input == name).map_or(self.encoding, |&(_, encoding)| encoding);
+        let (mut reader, encoding) = decode_input(reader, encoding).with_path(name)?;
+        let dialect = self.for_input(name, &mut reader)?;
+        Ok((reader, CsvDialect { encoding, ..dialect }))
+    }
+
+    fn for_input(&self, name: &str, reader: &mut dyn BufRead) -> Result<CsvDialect> {
+        //the last dialect given for an input wins, like any repeated option
+        let Some((_, file_dialect)) = self.file_dialects.iter().rev().find(|(input, _)| input == name) else {
+            return self.sniffed(name, reader);
+        };
+        let dialect = CsvDialect {
+            quote: file_dialect.quote.unwrap_or(self.quote),
+            has_header: file_dialect.has_header.unwrap_or(self.has_header),
+            ..self.clone()
+        };
+        match file_dialect.delimiter {
+            Delimiter::Char(delimiter) => Ok(CsvDialect { delimiter, sniff: false, ..dialect }),
+            Delimiter::Auto => {
+                //what was given for the input is kept, only the rest is detected
+                let sniffed = CsvDialect { sniff: true, ..dialect }.sniffed(name, reader)?;
+                Ok(CsvDialect {
+                    quote: file_dialect.quote.unwrap_or(sniffed.quote),
+                    has_header: file_dialect.has_header.unwrap_or(sniffed.has_header),
+                    ..sniffed
+                })
+            }
+        }
+    }
+
+    fn sniffed(&self, name: &str, reader: &mut dyn BufRead) -> Result<CsvDialect> {
+        if !self.sniff {
+            return Ok(self.clone());
         }
+        let sample = reader.fill_buf().with_path(name)?; //looks at what is buffered without consuming it
+        Ok(sniff_dialect(&String::from_utf8_lossy(sample), self))
+    }
+}
+
+fn check_delimiter(delimiter: char, quote: char, side: &str) -> Result<()> {
+    let message = if matches!(delimiter, '\n' | '\r') {
+        format!("{}delimiter {:?} cannot be a line break", side, delimiter)
+    } else if matches!(quote, '\n' | '\r') {
+        format!("{}quote character {:?} cannot be a line break", side, quote)
+    } else if delimiter == quote {
+        format!("{}delimiter {:?} is also the {}quote character", side, delimiter, side)
+    } else {
+        return Ok(());
     };
-    //estabilsh column mapping
-
-    let mut index_maps_by_file_index: Vec<Vec<usize>> = Vec::with_capacity(filenames.len());
-    
-    
-    let mut output_header_vec = key_columns.clone();
-    //read headers in other files to see if there are any new columns
-    for &filename in filenames.iter() {
-        let current_file = File::open(filename)?;
-        let current_reader = BufReader::new(current_file);
-        let mut current_lines = current_reader.lines();
-        // let current_header = current_lines.next().unwrap()?;
-        let current_header_vec = parse_next_line(&mut current_lines, delimiter)?.unwrap();
-        // output_header_vec.resize(max(current_header_vec.len(),output_header_vec.len()), String::new()); //this was meant to ensure capacity but likely not needed
-        index_maps_by_file_index.push(Vec::with_capacity(current_header_vec.len())); 
-        let last_index = index_maps_by_file_index.len() - 1;
-        let index_map: &mut Vec<usize> = &mut index_maps_by_file_index[last_index];
-        // let mut new_column_index_offset: usize = 0;
-        for header in current_header_vec.into_iter() {
-            match output_header_vec.iter().position(|x| *x == header){
-                Some(i) => { index_map.push(i); },
-                None => {//new column adds to output header
+    Err(CombineError::Config { message })
+}
+
+/// Field delimiter of an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Delimiter {
+    Char(char),
+    /// Detect it from the input's first lines
+    Auto,
+}
+
+const DELIMITER_FORMS: &str = "a single character, tab, comma, semicolon, pipe, space, \\t or \\xNN";
+
+/// A single character, a name or an escape, before line breaks are ruled out
+fn delimiter_char(s: &str) -> Option<char> {
+    let mut chars = s.chars();
+    match (s, chars.next(), chars.next()) {
+        ("tab" | "\\t", _, _) => Some('\t'),
+        ("comma", _, _) => Some(','),
+        ("semicolon", _, _) => Some(';'),
+        ("pipe", _, _) => Some('|'),
+        ("space", _, _) => Some(' '),
+        (_, Some(delimiter), None) => Some(delimiter),
+        _ => s.strip_prefix("\\x").filter(|hex| hex.len() == 2).and_then(|hex| u8::from_str_radix(hex, 16).ok()).map(char::from),
+    }
+}
+
+impl Delimiter {
+    /// Parses a single character, a name (`tab`, `comma`, `semicolon`, `pipe`, `space`) or an escape (`\t`, `\xNN`).
+    /// Line breaks are rejected, they always end a record
+    pub fn parse_char(s: &str) -> std::result::Result<char, String> {
+        match delimiter_char(s) {
+            Some(delimiter @ ('\n' | '\r')) => Err(format!("invalid delimiter {:?}, a line break cannot be a delimiter", delimiter)),
+            Some(delimiter) => Ok(delimiter),
+            None => Err(format!("invalid delimiter '{}', expected {}", s, DELIMITER_FORMS)),
+        }
+    }
+}
+
+/// Parses `auto` or anything `Delimiter::parse_char` takes
+impl FromStr for Delimiter {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s {
+            "auto" => Ok(Delimiter::Auto),
+            _ if delimiter_char(s).is_none() => Err(format!("invalid delimiter '{}', expected auto or {}", s, DELIMITER_FORMS)),
+            _ => Delimiter::parse_char(s).map(Delimiter::Char),
+        }
+    }
+}
+
+/// Dialect of a single input, see `CsvDialect::file_dialects`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct FileDialect {
+    /// Field delimiter, or `Delimiter::Auto` to detect it along with whatever else is not given
+    pub delimiter: Delimiter,
+    /// Quote character, `CsvDialect::quote` when not given
+    pub quote: Option<char>,
+    /// Whether the input starts with a header row, `CsvDialect::has_header` when not given
+    pub has_header: Option<bool>,
+}
+
+impl From<Delimiter> for FileDialect {
+    fn from(delimiter: Delimiter) -> Self {
+        FileDialect { delimiter, quote: None, has_header: None }
+    }
+}
+
+/// Parses `DELIMITER[,QUOTE][,header|noheader]`, e.g. `tab,',noheader`.  The delimiter is anything `Delimiter` parses,
+/// written as `comma` when it is one
+impl FromStr for FileDialect {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        let mut parts = s.split(',');
+        let mut dialect = FileDialect::from(parts.next().unwrap_or_default().parse::<Delimiter>()?);
+        for part in parts {
+            let mut chars = part.chars();
+            match (part, chars.next(), chars.next()) {
+                ("header", _, _) if dialect.has_header.is_none() => dialect.has_header = Some(true),
+                ("noheader", _, _) if dialect.has_header.is_none() => dialect.has_header = Some(false),
+                (_, Some(quote), None) if dialect.quote.is_none() && dialect.has_header.is_none() => dialect.quote = Some(quote),
+                _ => return Err(format!("invalid dialect '{}', expected DELIMITER[,QUOTE][,header|noheader]", s)),
+            }
+        }
+        Ok(dialect)
+    }
+}
+
+/// What to do with rows whose key columns match an earlier row
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum DuplicateHandling {
+    /// Write every row
+    #[default]
+    Keep,
+    /// Keep the first row for each key and skip the rest
+    Remove,
+    /// Combine rows with the same key, by default filling empty fields from later rows (see `MergeStrategy`)
+    Merge,
+}
+
+/// Which inputs must have every requested key column
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum KeyPresence {
+    /// Every input has every key column
+    #[default]
+    Every,
+    /// Each key column is in at least one input, inputs without it get the empty value as that part of their key
+    Any,
+}
+
+/// Which merged rows are written, by the inputs their key appears in.  Inputs count in the order given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum JoinMode {
+    /// Keys in every input
+    Inner,
+    /// Keys in the first input
+    Left,
+    /// Keys in the last input
+    Right,
+    /// Every key
+    #[default]
+    Full,
+    /// Keys in the first input and none of the others
+    Anti,
+}
+
+impl JoinMode {
+    fn includes(&self, sources: &[bool]) -> bool {
+        match self {
+            JoinMode::Inner => sources.iter().all(|&source| source),
+            JoinMode::Left => sources.first() == Some(&true),
+            JoinMode::Right => sources.last() == Some(&true),
+            JoinMode::Full => true,
+            JoinMode::Anti => sources.first() == Some(&true) && !sources[1..].contains(&true),
+        }
+    }
+}
+
+/// Which distinct rows are written with `DuplicateHandling::Remove`, by the inputs their key appears in.
+/// Inputs count in the order given
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum SetOperation {
+    /// Keys in any input, the same as only removing duplicates
+    #[default]
+    Union,
+    /// Keys in every input
+    Intersect,
+    /// Keys in the first input and none of the others
+    Except,
+    /// Keys in exactly one input
+    SymmetricDifference,
+}
+
+impl SetOperation {
+    fn includes(&self, sources: &[bool]) -> bool {
+        match self {
+            SetOperation::Union => true,
+            SetOperation::Intersect => sources.iter().all(|&source| source),
+            SetOperation::Except => sources.first() == Some(&true) && !sources[1..].contains(&true),
+            SetOperation::SymmetricDifference => sources.iter().filter(|&&source| source).count() == 1,
+        }
+    }
+}
+
+/// Which row survives when duplicates are removed
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum KeepDuplicate {
+    /// The first row read for each key
+    #[default]
+    First,
+    /// The last row read for each key
+    Last,
+    /// The row with the largest value in this column, the earlier one on a tie
+    Max(String),
+    /// The row with the smallest value in this column, the earlier one on a tie
+    Min(String),
+}
+
+/// Parses `first`, `last`, `max:COLUMN` and `min:COLUMN`
+impl FromStr for KeepDuplicate {
+    type Err = String;
+
+    fn from_str(s: &str) -> std::result::Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("max", column)) if !column.is_empty() => Ok(KeepDuplicate::Max(column.to_string())),
+            Some(("min", column)) if !column.is_empty() => Ok(KeepDuplicate::Min(column.to_string())),
+            None if s == "first" => Ok(KeepDuplicate::First),
+            None if s == "last" => Ok(KeepDuplicate::Last),
+            _ => Err(format!("invalid keep rule '{}', expected first, last, max:COLUMN or min:COLUMN", s)),
+        }
+    }
+}
+
+/// What to do with data rows that have a different number of fields than their file's header
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum RaggedRows {
+    /// Fail on any row that is shorter or longer than the header
+    Error,
+    /// Fill missing fields with the empty value, fail on rows with extra fields
+    #[default]
+    Pad,
+    /// Fill missing fields with the empty value and drop extra fields
+    Truncate,
+    /// Fill missing fields with the empty value and put extra fields in _extra_1, _extra_2, ... columns
+    Spill,
+}
+
+/// What happened during a combine that did not stop it
+#[derive(Debug, Default)]
+pub struct CombineSummary {
+    /// Names of the key columns, which start every row of the conflict and fuzzy match reports
+    pub key_columns: Vec<String>,
+    /// Recoverable problems found in the inputs, e.g. unterminated quotes closed in lenient mode
+    pub warnings: Vec<String>,
+    /// Data rows that were padded, truncated or spilled because their length did not match the header
+    pub ragged_rows: usize,
+    /// Merged columns whose rows disagreed, with `ConflictHandling::Report`
+    pub conflicts: Vec<MergeConflict>,
+    /// Rows whose key was matched to a different key with `fuzzy_keys`
+    pub fuzzy_matches: Vec<FuzzyMatch>,
+}
+
+fn read_header<R: BufRead>(reader: &mut CsvReader<R>, filename: &str) -> Result<Vec<String>> {
+    //every input needs a header row to map its columns into the output
+    match reader.next_record()? {
+        Some(header) => Ok(header),
+        None => Err(CombineError::Schema { path: filename.to_string(), line: 1, message: "file is empty, expected a header row".to_string() }),
+    }
+}
+
+/// A combined row, aligned to the combined header
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Record {
+    header: Arc<[String]>,
+    fields: Vec<String>,
+}
+
+impl Record {
+    /// The combined header, shared by every record of a run
+    pub fn header(&self) -> &[String] {
+        &self.header
+    }
+
+    pub fn fields(&self) -> &[String] {
+        &self.fields
+    }
+
+    /// Value of the named column, `None` if the combined header has no such column
+    pub fn get(&self, column: &str) -> Option<&str> {
+        let index = self.header.iter().position(|x| x == column)?;
+        Some(&self.fields[index])
+    }
+
+    pub fn into_fields(self) -> Vec<String> {
+        self.fields
+    }
+}
+
+/// One source of rows to combine
+pub enum Input {
+    /// A file on disk, opened again for every pass over it
+    Path(String),
+    /// Any other source, e.g. stdin.  It can only be read once, so it is kept open after its header is read,
+    /// or buffered in memory when a pass needs to see the whole input (`RaggedRows::Spill`)
+    Reader { name: String, reader: Box<dyn BufRead + Send> },
+}
+
+impl Input {
+    /// `name` stands in for the file name in errors and warnings
+    pub fn reader(name: impl Into<String>, reader: impl BufRead + Send + 'static) -> Self {
+        Input::Reader { name: name.into(), reader: Box::new(reader) }
+    }
+
+    pub fn name(&self) -> &str {
+        match self {
+            Input::Path(path) => path,
+            Input::Reader { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Debug for Input {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Input::Path(path) => f.debug_tuple("Path").field(path).finish(),
+            Input::Reader { name, .. } => f.debug_struct("Reader").field("name", name).finish_non_exhaustive(),
+        }
+    }
+}
+
+impl From<&str> for Input {
+    fn from(path: &str) -> Self {
+        Input::Path(path.to_string())
+    }
+}
+
+type InputReader = CsvReader<Box<dyn BufRead + Send>>;
+
+fn open_input_file(path: &str, dialect: &CsvDialect) -> Result<InputReader> {
+    let (reader, _) = decode_input(open_file(path)?, dialect.encoding).with_path(path)?;
+    Ok(CsvReader::from_reader(reader, path, dialect))
+}
+
+fn open_sniffed_input_file(path: &str, dialect: &CsvDialect) -> Result<(InputReader, CsvDialect)> {
+    let (reader, dialect) = dialect.open_input(path, open_file(path)?)?;
+    Ok((CsvReader::from_reader(reader, path, &dialect), dialect))
+}
+
+fn open_input_buffer(bytes: Arc<[u8]>, name: &str, dialect: &CsvDialect) -> InputReader {
+    CsvReader::from_reader(Box::new(Cursor::new(bytes)), name, dialect)
+}
+
+type CellCandidates = Vec<(String, usize, usize)>; //non-empty values of one merged column: value, file index, line
+
+/// Where the data pass picks an input up: reopen the file with its dialect, or carry on with a reader already past the header
+enum PendingInput {
+    Path(String, CsvDialect),
+    Open(InputReader),
+}
+
+/// Iterator over the combined rows of several files, in the same order they would be written
+///
+/// Headers are read when it is created, data rows as it is iterated.  With `DuplicateHandling::Merge`
+/// no rows come out until every file has been read.  Stops after the first error.
+pub struct CombinedRecords {
+    filenames: Vec<String>,
+    pending_inputs: Vec<Option<PendingInput>>,
+    empty_field_value: String,
+    duplicates: DuplicateHandling,
+    ragged_rows: RaggedRows,
+    header: Arc<[String]>,
+    key_len: usize,
+    index_maps_by_file_index: Vec<Vec<usize>>,
+    header_lens_by_file_index: Vec<usize>,
+    file_index: usize,
+    current_reader: Option<InputReader>,
+    seen_keys: HashSet<Vec<String>>,
+    key_normalizations: Vec<KeyNormalization>, //by key column
+    sort_columns: Vec<usize>,
+    merge_strategies: Vec<MergeStrategy>, //by output column, key columns are never merged
+    held_rows: Vec<Vec<String>>, //rows that can only be written once every file is read, in first-seen order
+    held_indexes: HashMap<Vec<String>, usize>, //key fields to the merged or kept row's position in held_rows
+    fuzzy_keys: Option<FuzzyKeys>,
+    join: JoinMode,
+    set_operation: SetOperation,
+    whole_row_keys: bool,
+    held_sources: Vec<Vec<bool>>, //by held row and input, whether the input had the row's key.  only filled for joins and set operations
+    held_keys: Vec<Vec<String>>, //by held row, the key fields it was first held under.  only filled when matching fuzzily
+    priorities_by_file_index: Vec<i32>,
+    prioritized: bool,
+    keep: KeepDuplicate,
+    keep_column: usize, //column compared by KeepDuplicate::Max and Min
+    held_priorities: Vec<Vec<i32>>, //by held row and column, priority of the file each value came from.  only filled when prioritized
+    held_output: Option<vec::IntoIter<Vec<String>>>,
+    conflict_handling: ConflictHandling,
+    held_candidates: Vec<Vec<CellCandidates>>, //by held row and column, only filled when conflicts are tracked
+    summary: CombineSummary,
+    failed: bool,
+}
+
+impl CombinedRecords {
+    /// Reads every input file's header and builds the combined schema
+    pub fn new(filenames: &[&str], key_columns: Option<&[&str]>, dialect: &CsvDialect, empty_field_value: &str, duplicates: DuplicateHandling, ragged_rows: RaggedRows) -> Result<Self> {
+        let inputs: Vec<Input> = filenames.iter().map(|&filename| Input::from(filename)).collect();
+        CombinedRecords::from_inputs(inputs, key_columns, dialect, empty_field_value, duplicates, ragged_rows, KeyPresence::Every)
+    }
+
+    /// Same as `new`, for inputs that are not all files.  Requested key columns are checked against `key_presence`,
+    /// key columns taken from the first header are not
+    pub fn from_inputs(inputs: Vec<Input>, key_columns: Option<&[&str]>, dialect: &CsvDialect, empty_field_value: &str, duplicates: DuplicateHandling, ragged_rows: RaggedRows, key_presence: KeyPresence) -> Result<Self> {
+        // Determine key columns: either from parameter or from first header
+        let mut key_columns: Option<Vec<String>> = key_columns.map(|cols| cols.iter().map(|s| s.to_string()).collect());
+        let requested_keys = key_columns.is_some();
+        let mut missing_keys: Vec<(String, Vec<String>)> = Vec::new(); //input name and the requested key columns its header lacks
+        //estabilsh column mapping
+
+        let mut filenames: Vec<String> = Vec::with_capacity(inputs.len());
+        let mut pending_inputs: Vec<Option<PendingInput>> = Vec::with_capacity(inputs.len());
+        let mut index_maps_by_file_index: Vec<Vec<usize>> = Vec::with_capacity(inputs.len());
+        let mut header_lens_by_file_index: Vec<usize> = Vec::with_capacity(inputs.len());
+        
+        
+        let mut output_header_vec = key_columns.clone().unwrap_or_default();
+        let mut spill_columns: Vec<usize> = Vec::new(); //output positions of the _extra_N columns, in order
+        dialect.validate()?;
+        if let Some((name, _)) = dialect.file_dialects.iter().find(|(name, _)| !inputs.iter().any(|input| input.name() == name)) {
+            return Err(CombineError::Config { message: format!("delimiter given for '{}', which is not an input", name) });
+        }
+        if let Some((name, _)) = dialect.file_encodings.iter().find(|(name, _)| !inputs.iter().any(|input| input.name() == name)) {
+            return Err(CombineError::Config { message: format!("encoding given for '{}', which is not an input", name) });
+        }
+        //read headers in all files to see if there are any new columns
+        for input in inputs {
+            let filename = input.name().to_string();
+            let reopen = matches!(input, Input::Path(_));
+            let (mut current_reader, data_reader, input_dialect) = match input {
+                Input::Path(path) => {
+                    let (current_reader, input_dialect) = open_sniffed_input_file(&path, dialect)?;
+                    (current_reader, None, input_dialect)
+                }
+                Input::Reader { name, reader } if ragged_rows == RaggedRows::Spill => {
+                    //the spill scan below reads the whole input, so buffer it (as UTF-8) to read it again for the data rows
+                    let (mut reader, input_dialect) = dialect.open_input(&name, reader)?;
+                    let mut bytes: Vec<u8> = Vec::new();
+                    reader.read_to_end(&mut bytes).with_path(&name)?;
+                    let bytes: Arc<[u8]> = bytes.into();
+                    let mut data_reader = open_input_buffer(Arc::clone(&bytes), &name, &input_dialect);
+                    if input_dialect.has_header {
+                        data_reader.next_record()?; //skip header
+                    }
+                    (open_input_buffer(bytes, &name, &input_dialect), Some(data_reader), input_dialect)
+                }
+                Input::Reader { name, reader } => {
+                    let (reader, input_dialect) = dialect.open_input(&name, reader)?;
+                    (CsvReader::from_reader(reader, &name, &input_dialect), None, input_dialect)
+                }
+            };
+            let mut current_header_vec = read_header(&mut current_reader, &filename)?;
+            if !input_dialect.has_header {
+                //the first row is data, columns are named by position instead
+                let names: Vec<String> = (1..=current_header_vec.len()).map(|number| format!("column_{}", number)).collect();
+                current_reader.unread_record(mem::replace(&mut current_header_vec, names));
+            }
+            let key_columns: &Vec<String> = key_columns.get_or_insert_with(|| {
+                //derive from first file
+                output_header_vec = current_header_vec.clone();
+                current_header_vec.clone()
+            });
+            let header_len = current_header_vec.len();
+            header_lens_by_file_index.push(header_len);
+            index_maps_by_file_index.push(Vec::with_capacity(current_header_vec.len())); 
+            let last_index = index_maps_by_file_index.len() - 1;
+            let index_map: &mut Vec<usize> = &mut index_maps_by_file_index[last_index];
+            for key_column in key_columns.iter() {
+                if current_header_vec.iter().filter(|x| *x == key_column).count() > 1 {
+                    return Err(CombineError::Key { path: filename, line: 1, message: format!("key column '{}' appears more than once in the header", key_column) });
+                }
+            }
+            if requested_keys {
+                let missing: Vec<String> = key_columns.iter().filter(|&key_column| !current_header_vec.contains(key_column)).cloned().collect();
+                if !missing.is_empty() {
+                    missing_keys.push((filename.clone(), missing));
+                }
+            }
+            for header in current_header_vec.into_iter() {
+                if spill_columns.iter().any(|&i| output_header_vec[i] == header) {
+                    let message = format!("column '{}' has the name of a column made for extra fields of an earlier input", header);
+                    return Err(CombineError::Schema { path: filename, line: 1, message });
+                }
+                match output_header_vec.iter().position(|x| *x == header){
+                    Some(i) => { index_map.push(i); },
+                    None => {//new column adds to output header
+                        index_map.push(output_header_vec.len());
+                        output_header_vec.push(header);
+                    }
+                }
+            }
+            if ragged_rows == RaggedRows::Spill {
+                //scan the rest of the file for the widest row so the extra columns can be part of the output header
+                let mut max_row_len = header_len;
+                while let Some(fields) = current_reader.next_record()? {
+                    max_row_len = max(max_row_len, fields.len());
+                }
+                for extra_index in 0..(max_row_len - header_len) {
+                    //extra columns are shared by every input, and their names skip the ones real columns already have
+                    if let Some(&i) = spill_columns.get(extra_index) {
+                        index_map.push(i);
+                        continue;
+                    }
+                    let extra_header = (1..).map(|number| format!("_extra_{}", number)).find(|name| !output_header_vec.contains(name)).unwrap();
+                    spill_columns.push(output_header_vec.len());
                     index_map.push(output_header_vec.len());
-                    output_header_vec.push(header);
+                    output_header_vec.push(extra_header);
+                }
+            }
+            let pending_input = match data_reader {
+                Some(data_reader) => PendingInput::Open(data_reader),
+                None if reopen => PendingInput::Path(filename.clone(), input_dialect),
+                None => PendingInput::Open(current_reader), //already past the header
+            };
+            pending_inputs.push(Some(pending_input));
+            filenames.push(filename);
+        }
+        match key_presence {
+            KeyPresence::Every if !missing_keys.is_empty() => return Err(CombineError::MissingKeys { missing: missing_keys }),
+            KeyPresence::Any if missing_keys.len() == filenames.len() => {
+                //only keys that no input has are a problem
+                let nowhere: Vec<String> = key_columns.iter().flatten()
+                    .filter(|&key_column| missing_keys.iter().all(|(_, missing)| missing.contains(key_column)))
+                    .cloned()
+                    .collect();
+                if !nowhere.is_empty() {
+                    let missing = missing_keys.into_iter().map(|(filename, _)| (filename, nowhere.clone())).collect();
+                    return Err(CombineError::MissingKeys { missing });
                 }
             }
+            _ => {}
         }
+        let merge_strategies = vec![MergeStrategy::default(); output_header_vec.len()];
+        let key_len = key_columns.map_or(0, |key_columns| key_columns.len());
+        let summary = CombineSummary { key_columns: output_header_vec[..key_len].to_vec(), ..CombineSummary::default() };
+        let priorities_by_file_index = vec![0; filenames.len()];
+        Ok(CombinedRecords {
+            filenames,
+            pending_inputs,
+            empty_field_value: empty_field_value.to_string(),
+            duplicates,
+            ragged_rows,
+            header: output_header_vec.into(),
+            key_len,
+            index_maps_by_file_index,
+            header_lens_by_file_index,
+            file_index: 0,
+            current_reader: None,
+            seen_keys: HashSet::new(),
+            key_normalizations: vec![KeyNormalization::default(); key_len],
+            sort_columns: Vec::new(),
+            merge_strategies,
+            held_rows: Vec::new(),
+            held_indexes: HashMap::new(),
+            fuzzy_keys: None,
+            join: JoinMode::default(),
+            set_operation: SetOperation::default(),
+            whole_row_keys: false,
+            held_sources: Vec::new(),
+            held_keys: Vec::new(),
+            priorities_by_file_index,
+            prioritized: false,
+            keep: KeepDuplicate::default(),
+            keep_column: 0,
+            held_priorities: Vec::new(),
+            held_output: None,
+            conflict_handling: ConflictHandling::default(),
+            held_candidates: Vec::new(),
+            summary,
+            failed: false,
+        })
     }
-    //write output header
-    let output_file = File::create(output_filename)?;
-    let mut output_writer = BufWriter::new(output_file);
-    writeln!(output_writer, "{}", output_header_vec.join(&delimiter.to_string()))?;
-    let mut seen_keys: HashSet<Vec<String>> = HashSet::new();
-    let mut merged_rows: HashMap<Vec<String>, Vec<String>> = HashMap::new();
-    //read data rows and write to output
-    for (file_index, &filename) in filenames.iter().enumerate(){
-        let current_file = File::open(filename)?;
-        let current_reader = BufReader::new(current_file);
-        let mut current_lines = current_reader.lines();
-        let _current_header = parse_next_line(&mut current_lines, delimiter)?; //skip header
-        let index_map: &Vec<usize> = &index_maps_by_file_index[file_index];
-        while let Some(fields) = parse_next_line(&mut current_lines, delimiter)? {
-            let mut output_fields: Vec<String> = vec![empty_field_value.to_string(); output_header_vec.len()];
+
+    /// How every key column is normalized before rows are compared.  Replaces any per-column normalization set before
+    pub fn normalize_keys(mut self, normalization: KeyNormalization) -> Self {
+        self.key_normalizations.fill(normalization);
+        self
+    }
+
+    /// How one key column is normalized before rows are compared.  The output still has the value as read
+    pub fn normalize_key(mut self, column: &str, normalization: KeyNormalization) -> Result<Self> {
+        match self.header[..self.key_len].iter().position(|x| x == column) {
+            Some(i) => {
+                self.key_normalizations[i] = normalization;
+                Ok(self)
+            }
+            None => Err(CombineError::Config { message: format!("'{}' is not a key column and cannot be normalized", column) }),
+        }
+    }
+
+    /// Sorts the output by the given columns, compared as numbers when both values are numeric and as text otherwise.
+    /// Rows that compare equal keep their input order.  Like merging, this holds every row back until all files are read
+    pub fn sort_by(mut self, columns: &[&str]) -> Result<Self> {
+        let mut sort_columns: Vec<usize> = Vec::with_capacity(columns.len());
+        for &column in columns {
+            match self.header.iter().position(|x| x == column) {
+                Some(i) => sort_columns.push(i),
+                None => return Err(CombineError::Config { message: format!("sort column '{}' is not in the combined header", column) }),
+            }
+        }
+        self.sort_columns = sort_columns;
+        Ok(self)
+    }
+
+    /// How every non-key column is merged with `DuplicateHandling::Merge`.  Replaces any per-column strategy set before
+    pub fn merge_strategy(mut self, strategy: MergeStrategy) -> Self {
+        self.merge_strategies.fill(strategy);
+        self
+    }
+
+    /// How one column is merged with `DuplicateHandling::Merge`
+    pub fn column_merge_strategy(mut self, column: &str, strategy: MergeStrategy) -> Result<Self> {
+        match self.header.iter().position(|x| x == column) {
+            Some(i) if i < self.key_len => Err(CombineError::Config { message: format!("'{}' is a key column and cannot have a merge strategy", column) }),
+            Some(i) => {
+                self.merge_strategies[i] = strategy;
+                Ok(self)
+            }
+            None => Err(CombineError::Config { message: format!("merge column '{}' is not in the combined header", column) }),
+        }
+    }
+
+    /// Ranks an input above (higher) or below (lower) the others, default 0.  When duplicates are removed the row from the
+    /// highest-priority file is kept, and when merging its non-empty values win whatever the merge strategy.
+    /// Inputs with the same priority keep the usual order.  Removing duplicates then holds rows back until all files are read
+    pub fn priority(mut self, input: &str, priority: i32) -> Result<Self> {
+        let mut found = false;
+        for (file_index, filename) in self.filenames.iter().enumerate() {
+            if filename == input {
+                self.priorities_by_file_index[file_index] = priority;
+                found = true;
+            }
+        }
+        if !found {
+            return Err(CombineError::Config { message: format!("priority given for '{}', which is not an input", input) });
+        }
+        self.prioritized = true;
+        Ok(self)
+    }
+
+    /// Which row survives with `DuplicateHandling::Remove`.  It is written where its key was first seen.
+    /// Empty values never win a max or min comparison.  Anything but `First` holds rows back until all files are read
+    pub fn keep_duplicate(mut self, keep: KeepDuplicate) -> Result<Self> {
+        if let KeepDuplicate::Max(column) | KeepDuplicate::Min(column) = &keep {
+            match self.header.iter().position(|x| x == column) {
+                Some(i) => self.keep_column = i,
+                None => return Err(CombineError::Config { message: format!("keep column '{}' is not in the combined header", column) }),
+            }
+        }
+        self.keep = keep;
+        Ok(self)
+    }
+
+    /// Lets rows whose keys are close but not equal count as duplicates, after any key normalization.
+    /// A row goes to the closest key held so far, and every such match is listed in `CombineSummary::fuzzy_matches`.
+    /// Makes `DuplicateHandling::Remove` hold rows back until all files are read
+    pub fn fuzzy_keys(mut self, fuzzy_keys: FuzzyKeys) -> Self {
+        self.fuzzy_keys = Some(fuzzy_keys);
+        self
+    }
+
+    /// Which merged rows are written, by the inputs their key appears in.  Only used with `DuplicateHandling::Merge`
+    pub fn join(mut self, join: JoinMode) -> Self {
+        self.join = join;
+        self
+    }
+
+    /// Which distinct rows are written, by the inputs their key appears in.  Only used with `DuplicateHandling::Remove`.
+    /// Anything but `Union` holds rows back until all files are read
+    pub fn set_operation(mut self, set_operation: SetOperation) -> Self {
+        self.set_operation = set_operation;
+        self
+    }
+
+    /// Compare every column of the combined header instead of the key columns when looking for duplicates.
+    /// Key normalization still only applies to the key columns
+    pub fn whole_row_keys(mut self, whole_row_keys: bool) -> Self {
+        self.whole_row_keys = whole_row_keys;
+        self
+    }
+
+    /// What to do when merged rows have different non-empty values for a column, whatever the merge strategy.
+    /// Tracking conflicts keeps every merged value and where it came from in memory until all files are read
+    pub fn conflicts(mut self, conflict_handling: ConflictHandling) -> Self {
+        self.conflict_handling = conflict_handling;
+        self
+    }
+
+    /// The combined header: key columns first, then every other column in the order first seen
+    pub fn header(&self) -> &[String] {
+        &self.header
+    }
+
+    /// Warnings and counts collected so far.  Complete once the iterator is exhausted
+    pub fn summary(&self) -> &CombineSummary {
+        &self.summary
+    }
+
+    pub fn into_summary(self) -> CombineSummary {
+        self.summary
+    }
+
+    pub(crate) fn key_len(&self) -> usize {
+        self.key_len
+    }
+
+    pub(crate) fn filename(&self, file_index: usize) -> &str {
+        &self.filenames[file_index]
+    }
+
+    /// Output column of each of an input's columns
+    pub(crate) fn input_columns(&self, file_index: usize) -> &[usize] {
+        &self.index_maps_by_file_index[file_index]
+    }
+
+    /// Input index and line of the row handed out last.  Only meaningful while rows are not held back
+    pub(crate) fn position(&self) -> (usize, usize) {
+        (self.file_index, self.current_reader.as_ref().map_or(0, |reader| reader.record_line()))
+    }
+
+    fn record(&self, fields: Vec<String>) -> Record {
+        Record { header: Arc::clone(&self.header), fields }
+    }
+
+    fn collect_conflicts(&mut self, held_rows: &[Vec<String>]) -> Vec<MergeConflict> {
+        //a column conflicts when its non-empty values are not all the same
+        let mut conflicts: Vec<MergeConflict> = Vec::new();
+        for (row, row_candidates) in held_rows.iter().zip(mem::take(&mut self.held_candidates)) {
+            for (column_index, candidates) in row_candidates.into_iter().enumerate() {
+                if candidates.iter().all(|(value, _, _)| *value == candidates[0].0) {
+                    continue;
+                }
+                conflicts.push(MergeConflict {
+                    key: self.header[..self.key_len].iter().cloned().zip(row[..self.key_len].iter().cloned()).collect(),
+                    column: self.header[column_index].clone(),
+                    candidates: candidates.into_iter()
+                        .map(|(value, file_index, line)| ConflictCandidate { value, path: self.filenames[file_index].clone(), line })
+                        .collect(),
+                });
+            }
+        }
+        conflicts
+    }
+
+    pub(crate) fn match_key(&self, fields: &[String]) -> Vec<String> {
+        //key columns as compared, after any normalization
+        let key_fields = if self.whole_row_keys { fields } else { &fields[..self.key_len] };
+        key_fields.iter().enumerate().map(|(i, field)| match self.key_normalizations.get(i) {
+            Some(normalization) => normalization.apply(field).into_owned(),
+            None => field.clone(),
+        }).collect()
+    }
+
+    fn find_held_row(&mut self, key_fields: &[String], fields: &[String], line: usize) -> Option<usize> {
+        //an equal key always wins, only then is the closest held key looked for
+        if let Some(&held_index) = self.held_indexes.get(key_fields) {
+            return Some(held_index);
+        }
+        let fuzzy_keys = self.fuzzy_keys?;
+        let (held_index, _) = fuzzy_keys.closest(key_fields, &self.held_keys)?;
+        self.summary.fuzzy_matches.push(FuzzyMatch {
+            key: self.header[..self.key_len].iter().cloned().zip(self.held_rows[held_index][..self.key_len].iter().cloned()).collect(),
+            matched: fields[..self.key_len].to_vec(),
+            path: self.filenames[self.file_index].clone(),
+            line,
+            score: fuzzy_keys.score(key_fields, &self.held_keys[held_index]),
+        });
+        Some(held_index)
+    }
+
+    fn hold_keyed_row(&mut self, key_fields: Vec<String>, fields: Vec<String>) {
+        if self.fuzzy_keys.is_some() {
+            self.held_keys.push(key_fields.clone());
+        }
+        self.held_indexes.insert(key_fields, self.held_rows.len());
+        self.held_rows.push(fields);
+    }
+
+    fn tracks_sources(&self) -> bool {
+        match self.duplicates {
+            DuplicateHandling::Merge => self.join != JoinMode::Full,
+            DuplicateHandling::Remove => self.set_operation != SetOperation::Union,
+            DuplicateHandling::Keep => false,
+        }
+    }
+
+    fn mark_source(&mut self, held_index: Option<usize>) {
+        let row_index = held_index.unwrap_or(self.held_rows.len());
+        if row_index == self.held_sources.len() {
+            self.held_sources.push(vec![false; self.filenames.len()]);
+        }
+        self.held_sources[row_index][self.file_index] = true;
+    }
+
+    fn drop_excluded_rows(&mut self, held_rows: &mut Vec<Vec<String>>) {
+        //conflicts are only looked for in rows that are written, so their candidates go with them
+        let included: Vec<bool> = self.held_sources.iter().map(|sources| match self.duplicates {
+            DuplicateHandling::Merge => self.join.includes(sources),
+            _ => self.set_operation.includes(sources),
+        }).collect();
+        let mut included_rows = included.iter();
+        held_rows.retain(|_| *included_rows.next().unwrap());
+        if !self.held_candidates.is_empty() {
+            let mut included_candidates = included.iter();
+            self.held_candidates.retain(|_| *included_candidates.next().unwrap());
+        }
+    }
+
+    fn holds_rows(&self) -> bool {
+        self.duplicates == DuplicateHandling::Merge || !self.sort_columns.is_empty() || self.replaces_kept_rows()
+    }
+
+    fn replaces_kept_rows(&self) -> bool {
+        //a later duplicate can only win over the kept row if there is a rule other than keeping the first one.
+        //fuzzy matching compares against every kept key and set operations need to know every input a key is in, so they are held as well
+        self.duplicates == DuplicateHandling::Remove && (self.prioritized || self.keep != KeepDuplicate::First || self.fuzzy_keys.is_some() || self.tracks_sources())
+    }
+
+    fn wins_over_kept_row(&self, held_index: usize, fields: &[String], priority: i32) -> bool {
+        let kept_priority = self.held_priorities[held_index][0];
+        if priority != kept_priority {
+            return priority > kept_priority;
+        }
+        let wanted = match self.keep {
+            KeepDuplicate::First => return false,
+            KeepDuplicate::Last => return true,
+            KeepDuplicate::Max(_) => Ordering::Greater,
+            KeepDuplicate::Min(_) => Ordering::Less,
+        };
+        let value = &fields[self.keep_column];
+        let kept_value = &self.held_rows[held_index][self.keep_column];
+        if merge_strategy::is_empty(value, &self.empty_field_value) {
+            false
+        } else if merge_strategy::is_empty(kept_value, &self.empty_field_value) {
+            true
+        } else {
+            compare_fields(value, kept_value) == wanted
+        }
+    }
+
+    fn next_row(&mut self) -> Result<Option<Record>> {
+        //reads data rows until one is ready to hand out.  duplicates are skipped and merged rows held back until the end
+        loop {
+            if let Some(held_output) = self.held_output.as_mut() {
+                return Ok(held_output.next().map(|fields| self.record(fields)));
+            }
+            let Some(current_reader) = self.current_reader.as_mut() else {
+                if self.file_index == self.filenames.len() { //all files read
+                    if !self.holds_rows() {
+                        return Ok(None);
+                    }
+                    let mut held_rows = mem::take(&mut self.held_rows);
+                    if self.tracks_sources() {
+                        self.drop_excluded_rows(&mut held_rows);
+                    }
+                    if self.conflict_handling != ConflictHandling::Ignore {
+                        let conflicts = self.collect_conflicts(&held_rows);
+                        if self.conflict_handling == ConflictHandling::Fail && !conflicts.is_empty() {
+                            let key_columns = self.summary.key_columns.clone();
+                            return Err(CombineError::Conflict { key_columns, conflicts }); //nothing merged has been handed out yet
+                        }
+                        self.summary.conflicts = conflicts;
+                    }
+                    if !self.sort_columns.is_empty() {
+                        held_rows.sort_by(|a, b| compare_rows(a, b, &self.sort_columns)); //stable, so ties stay in first-seen order
+                    }
+                    self.held_output = Some(held_rows.into_iter()); //write held rows now
+                    continue;
+                }
+                let current_reader = match self.pending_inputs[self.file_index].take() {
+                    Some(PendingInput::Path(path, dialect)) => {
+                        let mut current_reader = open_input_file(&path, &dialect)?;
+                        if dialect.has_header {
+                            let _current_header = current_reader.next_record()?; //skip header
+                        }
+                        current_reader
+                    }
+                    Some(PendingInput::Open(current_reader)) => current_reader,
+                    None => unreachable!("each input is only started once"),
+                };
+                self.current_reader = Some(current_reader);
+                continue;
+            };
+            let Some(mut fields) = current_reader.next_record()? else {
+                self.summary.warnings.extend(current_reader.take_warnings());
+                self.current_reader = None;
+                self.file_index += 1;
+                continue;
+            };
+            let line = current_reader.record_line();
+            let index_map: &Vec<usize> = &self.index_maps_by_file_index[self.file_index];
+            let header_len = self.header_lens_by_file_index[self.file_index];
+            if fields.len() != header_len {
+                if self.ragged_rows == RaggedRows::Error || (fields.len() > header_len && self.ragged_rows == RaggedRows::Pad) {
+                    let message = format!("row has {} fields but the header has {}", fields.len(), header_len);
+                    return Err(CombineError::Schema { path: self.filenames[self.file_index].clone(), line, message });
+                }
+                fields.truncate(index_map.len()); //only does anything for RaggedRows::Truncate, spilled rows always fit
+                self.summary.ragged_rows += 1;
+            }
+            let field_count = fields.len();
+            let mut output_fields: Vec<String> = vec![self.empty_field_value.clone(); self.header.len()];
             for (field_index, field) in fields.into_iter().enumerate() {
                 let output_index = index_map[field_index];
                 output_fields[output_index] = field;
             }
+            let key_len = self.key_len;
             //check for duplicates if needed
-            if remove_duplicates {
-                let key_fields: Vec<String> = output_fields[..key_columns.len()].to_vec();
-                if seen_keys.contains(&key_fields){
-                    continue;//skipping the duplicate.  also skips the write below
+            let priority = self.priorities_by_file_index[self.file_index];
+            if self.replaces_kept_rows() {
+                let key_fields: Vec<String> = self.match_key(&output_fields);
+                let held_index = self.find_held_row(&key_fields, &output_fields, line);
+                if self.tracks_sources() {
+                    self.mark_source(held_index);
+                }
+                if let Some(held_index) = held_index {
+                    if self.wins_over_kept_row(held_index, &output_fields, priority) { //winning row takes the kept row's place
+                        self.held_rows[held_index] = output_fields;
+                        self.held_priorities[held_index].fill(priority);
+                    }
+                } else {
+                    self.hold_keyed_row(key_fields, output_fields);
+                    self.held_priorities.push(vec![priority; self.header.len()]);
+                }
+                continue; //hold back until all files are read
+            }
+            if self.duplicates == DuplicateHandling::Remove {
+                let key_fields: Vec<String> = self.match_key(&output_fields);
+                if self.seen_keys.contains(&key_fields){
+                    continue;//skipping the duplicate
                 } else {
-                    seen_keys.insert(key_fields);
+                    self.seen_keys.insert(key_fields);
                 }
             }
-            if merge_duplicates {
-                let key_fields: Vec<String> = output_fields[..key_columns.len()].to_vec();
-                if let Some(existing_fields) = merged_rows.get_mut(&key_fields){ //found existing row to merge into
-                    for i in key_columns.len()..output_fields.len() {
-                        if existing_fields[i - key_columns.len()] == empty_field_value {
-                            existing_fields[i - key_columns.len()] = mem::take(&mut output_fields[i]);
+            if self.duplicates == DuplicateHandling::Merge {
+                let key_fields: Vec<String> = self.match_key(&output_fields);
+                let held_index = self.find_held_row(&key_fields, &output_fields, line);
+                if self.conflict_handling != ConflictHandling::Ignore {
+                    let row_index = held_index.unwrap_or(self.held_rows.len());
+                    if row_index == self.held_candidates.len() {
+                        self.held_candidates.push(vec![Vec::new(); self.header.len()]);
+                    }
+                    for &i in &self.index_maps_by_file_index[self.file_index][..field_count] {
+                        if i >= key_len && !merge_strategy::is_empty(&output_fields[i], &self.empty_field_value) {
+                            self.held_candidates[row_index][i].push((output_fields[i].clone(), self.file_index, line));
+                        }
+                    }
+                }
+                if self.tracks_sources() {
+                    self.mark_source(held_index);
+                }
+                if let Some(merged_index) = held_index { //found existing row to merge into
+                    let existing_fields = &mut self.held_rows[merged_index];
+                    //only columns this row has take part, a file without the column must not overwrite it
+                    for &i in &self.index_maps_by_file_index[self.file_index][..field_count] {
+                        if i < key_len {
+                            continue;
+                        }
+                        let value = mem::take(&mut output_fields[i]);
+                        let merged = if self.prioritized {
+                            self.merge_strategies[i].merge_with_priority(&mut existing_fields[i], &mut self.held_priorities[merged_index][i], value, priority, &self.empty_field_value)
+                        } else {
+                            self.merge_strategies[i].merge(&mut existing_fields[i], value, &self.empty_field_value)
+                        };
+                        if let Err(message) = merged {
+                            let message = format!("{} in column '{}'", message, self.header[i]);
+                            return Err(CombineError::Schema { path: self.filenames[self.file_index].clone(), line, message });
                         }
                     }
                 } else { //new row to possibly merge into later
-                    merged_rows.insert(key_fields, output_fields[key_columns.len()..].to_vec());
+                    self.hold_keyed_row(key_fields, output_fields);
+                    if self.prioritized {
+                        self.held_priorities.push(vec![priority; self.header.len()]);
+                    }
                 }
-                continue; //skip writing now, will write later
+                continue; //hold back until all files are read
+            }
+            if self.holds_rows() {
+                self.held_rows.push(output_fields);
+                continue; //sorted once all files are read
             }
-            writeln!(output_writer, "{}", output_fields.join(&delimiter.to_string()))?; //write row immediately.  
+            return Ok(Some(self.record(output_fields)));
         }
     }
-    if merge_duplicates{ //write merged rows now
-        for (key_fields, value_fields) in merged_rows.into_iter(){
-            write!(output_writer, "{}", key_fields.join(&delimiter.to_string()))?;
-            if !value_fields.is_empty() {
-                write!(output_writer, "{}{}", delimiter, value_fields.join(&delimiter.to_string()))?;
-            }
-            writeln!(output_writer)?;
+}
+
+fn compare_fields(a: &str, b: &str) -> Ordering {
+    //numbers sort by value so 9 comes before 10.  anything else, including a number against text, sorts by its bytes
+    match (a.parse::<f64>(), b.parse::<f64>()) {
+        (Ok(x), Ok(y)) if x.is_finite() && y.is_finite() => x.total_cmp(&y).then_with(|| a.cmp(b)),
+        _ => a.cmp(b),
+    }
+}
+
+fn compare_rows(a: &[String], b: &[String], sort_columns: &[usize]) -> Ordering {
+    sort_columns.iter().map(|&i| compare_fields(&a[i], &b[i])).find(|ordering| ordering.is_ne()).unwrap_or(Ordering::Equal)
+}
+
+impl Iterator for CombinedRecords {
+    type Item = Result<Record>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed {
+            return None;
         }
+        let row = self.next_row();
+        self.failed = row.is_err();
+        row.transpose()
     }
-    Ok(())
 }
+
+pub(crate) fn write_records<W: Write>(mut records: CombinedRecords, writer: W, dialect: &CsvDialect, output_name: &str) -> Result<CombineSummary> {
+    //write output header
+    let mut output_writer = CsvWriter::new(BufWriter::new(EncodingWriter::new(writer, dialect.output_encoding)), dialect.output_delimiter(), dialect.quote_style).quote(dialect.output_quote);
+    output_writer.write_record(records.header()).with_path(output_name)?;
+    //read data rows and write to output
+    for record in records.by_ref() {
+        output_writer.write_record(record?.fields()).with_path(output_name)?;
+    }
+    output_writer.flush().with_path(output_name)?;
+    Ok(records.into_summary())
+}
+
+/// Same as `combine_files_by_keys`, but writes to any sink, e.g. stdout or an in-memory buffer
+pub fn combine_files_into<W: Write>(filenames: &[&str], writer: W, key_columns: Option<&[&str]>, dialect: &CsvDialect, empty_field_value: &str, duplicates: DuplicateHandling, ragged_rows: RaggedRows) -> Result<CombineSummary> {
+    let records = CombinedRecords::new(filenames, key_columns, dialect, empty_field_value, duplicates, ragged_rows)?;
+    write_records(records, writer, dialect, OUTPUT_NAME)
+}
+
+pub fn combine_files_by_keys(filenames: &[&str], output_filename: &str, key_columns: Option<&[&str]>,dialect: &CsvDialect,empty_field_value: &str,duplicates: DuplicateHandling,ragged_rows: RaggedRows) -> Result<CombineSummary> {
+    let records = CombinedRecords::new(filenames, key_columns, dialect, empty_field_value, duplicates, ragged_rows)?;
+    let output_file = File::create(output_filename).with_path(output_filename)?;
+    write_records(records, output_file, dialect, output_filename)
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
     use std::fs;
+    use std::io::{BufRead, BufReader, Result};
 
     #[test]
     fn test_level1_same_schema() -> Result<()> {
@@ -168,10 +1165,10 @@ mod tests {
             &["csv_samples/employees1.csv", "csv_samples/employees2.csv"],
             output,
             None,
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            false,
-            false
+            DuplicateHandling::Keep,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
@@ -204,10 +1201,10 @@ mod tests {
             &["csv_samples/employees1.csv", "csv_samples/employees3.csv"],
             output,
             None,
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            false,
-            false
+            DuplicateHandling::Keep,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
@@ -224,12 +1221,11 @@ mod tests {
         assert_eq!(line_counter, 11, "Output should have 11 lines (1 header + 10 data rows)");
         // println!("************************");
         // TODO: Data should match despite different column order
-        let output_reader = BufReader::new(File::open(output)?);
-        let mut output_lines = output_reader.lines();
-        let header = parse_next_line(&mut output_lines, ',')?.unwrap();
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
+        let header = output_reader.next_record()?.unwrap();
         let expected_header = vec!["id".to_string(), "name".to_string(), "department".to_string(), "salary".to_string()];
         assert_eq!(header, expected_header, "Header should match expected order");
-        while let Some(fields) = parse_next_line(&mut output_lines, ',')? {
+        while let Some(fields) = output_reader.next_record()? {
             assert_eq!(fields.len(), 4, "Each data row should have 4 fields");
             let id_field = &fields[0];
             assert!(id_field.parse::<i32>().is_ok(), "ID field should be an integer!");
@@ -247,18 +1243,16 @@ mod tests {
             &["csv_samples/employees1.csv", "csv_samples/employees4.csv"],
             output,
             None,
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            false,
-            false
+            DuplicateHandling::Keep,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
         
         // Verify merged schema includes all columns (union)
-        let output_file = File::open(output)?;
-        let output_reader = BufReader::new(output_file);
-        let mut output_lines = output_reader.lines();
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
         
         // println!("*****Output file contents:");
         // for line in &output_lines {
@@ -266,14 +1260,14 @@ mod tests {
         // }
         // println!("************************");
         
-        let header_fields = parse_next_line(&mut output_lines, ',')?.unwrap();
+        let header_fields = output_reader.next_record()?.unwrap();
         assert!(header_fields.contains(&"gender".to_string()), "Header should include 'gender' column");
         assert_eq!(header_fields.len(), 5, "Header should have 5 columns (including gender)");
         
         // Verify missing values are filled with "EMPTY"
         let gender_index = header_fields.iter().position(|x| x == "gender").unwrap();
         for _ in 0..5 { // First 5 rows from employees1 (no gender)
-            let fields = parse_next_line(&mut output_lines, ',')?.unwrap();
+            let fields = output_reader.next_record()?.unwrap();
             assert_eq!(fields[gender_index], "EMPTY", "Missing gender field should be 'EMPTY'");
         }
         
@@ -290,10 +1284,10 @@ mod tests {
             &["csv_samples/employees1.csv", "csv_samples/employees5.csv"],
             output,
             None,
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            false,
-            false
+            DuplicateHandling::Keep,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
@@ -316,6 +1310,98 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn test_quoted_field_roundtrip() -> Result<()> {
+        // Encoding the decoded fields must round-trip through the parser
+        let output = "csv_testing_output/test_output_quoted_roundtrip.csv";
+        combine_files_by_keys(&["csv_samples/employees5.csv"], output, None, &CsvDialect::default(), "EMPTY", DuplicateHandling::Keep, RaggedRows::Pad)?;
+        let mut expected_reader = CsvReader::open("csv_samples/employees5.csv", &CsvDialect::default())?;
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
+        while let Some(expected) = expected_reader.next_record()? {
+            let actual = output_reader.next_record()?.unwrap();
+            assert_eq!(actual, expected, "Row should survive a write/read round trip");
+        }
+        assert!(output_reader.next_record()?.is_none(), "Output should not have extra rows");
+
+        // Cleanup
+        let _ = fs::remove_file(output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_ragged_rows() -> Result<()> {
+        // employees9 has one short row (line 3) and two long rows (lines 4 and 5)
+        let output = "csv_testing_output/test_output_ragged.csv";
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees9.csv"];
+        let combine = |ragged_rows| combine_files_by_keys(&files, output, None, &CsvDialect::default(), "EMPTY", DuplicateHandling::Keep, ragged_rows);
+
+        let error = combine(RaggedRows::Error).expect_err("Short row should be an error");
+        assert!(error.to_string().contains("employees9.csv:3:"), "Error should point at line 3: {}", error);
+        let error = combine(RaggedRows::Pad).expect_err("Long row should be an error");
+        assert!(error.to_string().contains("employees9.csv:4:"), "Error should point at line 4: {}", error);
+
+        let summary = combine(RaggedRows::Truncate)?;
+        assert_eq!(summary.ragged_rows, 3, "All three ragged rows should be counted");
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
+        assert_eq!(output_reader.next_record()?.unwrap().len(), 4, "Truncate should not add columns");
+
+        let summary = combine(RaggedRows::Spill)?;
+        assert_eq!(summary.ragged_rows, 3);
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
+        let header = output_reader.next_record()?.unwrap();
+        assert_eq!(header[4..], ["_extra_1", "_extra_2"], "Extra fields should get their own columns");
+        let rows: Vec<Vec<String>> = std::iter::from_fn(|| output_reader.next_record().transpose()).collect::<std::result::Result<_, CombineError>>()?;
+        assert_eq!(rows[6], ["37", "Xavier Cole", "Sales", "EMPTY", "EMPTY", "EMPTY"]);
+        assert_eq!(rows[7], ["38", "Yara Singh", "Marketing", "79000", "remote", "part-time"]);
+        assert_eq!(rows[8], ["39", "Zack Fields", "HR", "64000", "contractor", "EMPTY"]);
+
+        // Cleanup
+        let _ = fs::remove_file(output);
+        Ok(())
+    }
+
+    #[test]
+    fn test_spill_column_names() -> crate::Result<()> {
+        let spill = |inputs: Vec<Input>| CombinedRecords::from_inputs(inputs, None, &CsvDialect::default(), "", DuplicateHandling::Keep, RaggedRows::Spill, KeyPresence::Every);
+        let records = spill(vec![Input::reader("a.csv", "id,_extra_1\n1,a,b\n".as_bytes()), Input::reader("b.csv", "id\n2,c\n".as_bytes())])?;
+        assert_eq!(records.header(), ["id", "_extra_1", "_extra_2"], "A real _extra_1 column should not take extra fields");
+        let rows: Vec<Vec<String>> = records.map(|record| record.map(Record::into_fields)).collect::<crate::Result<_>>()?;
+        assert_eq!(rows, [["1", "a", "b"], ["2", "", "c"]]);
+
+        let error = spill(vec![Input::reader("a.csv", "id\n1,a\n".as_bytes()), Input::reader("b.csv", "id,_extra_1\n2,b\n".as_bytes())]).err().expect("Names should not clash");
+        assert!(matches!(&error, CombineError::Schema { path, line: 1, .. } if path == "b.csv"), "Got {:?}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_error_variants() {
+        let output = "csv_testing_output/test_output_errors.csv";
+        let combine = |files: &[&str], keys: Option<&[&str]>| combine_files_by_keys(files, output, keys, &CsvDialect::default(), "EMPTY", DuplicateHandling::Keep, RaggedRows::Pad);
+
+        let error = combine(&["csv_samples/employees1.csv", "csv_samples/missing.csv"], None).unwrap_err();
+        assert!(matches!(error, CombineError::InputNotFound { ref path } if path == "csv_samples/missing.csv"), "Got {:?}", error);
+        let error = combine(&["csv_samples/employees1.csv", "csv_samples/employees8.csv"], None).unwrap_err();
+        assert!(matches!(error, CombineError::Parse(ref e) if e.line == 3), "Got {:?}", error);
+        let error = combine(&["csv_samples/employees9.csv"], None).unwrap_err();
+        assert!(matches!(error, CombineError::Schema { line: 4, .. }), "Got {:?}", error);
+        let error = combine(&["csv_samples/employees1.csv", "csv_samples/employees10.csv"], Some(&["id"])).unwrap_err();
+        assert!(matches!(error, CombineError::Key { line: 1, .. }), "Got {:?}", error);
+
+        let _ = fs::remove_file(output);
+    }
+
+    #[test]
+    fn test_combine_into_writer() -> Result<()> {
+        let mut buffer: Vec<u8> = Vec::new();
+        combine_files_into(&["csv_samples/employees1.csv", "csv_samples/employees3.csv"], &mut buffer, None, &CsvDialect::default(), "EMPTY", DuplicateHandling::Keep, RaggedRows::Pad)?;
+        let contents = String::from_utf8(buffer).unwrap();
+        let lines: Vec<&str> = contents.lines().collect();
+        assert_eq!(lines.len(), 11, "Output should have 11 lines (1 header + 10 data rows)");
+        assert_eq!(lines[0], "id,name,department,salary");
+        assert_eq!(lines[6], "11,Karen Anderson,Sales,73000", "Columns should be realigned");
+        Ok(())
+    }
+
     #[test]
     fn test_level5_multiline_fields() -> Result<()> {
         // Level 5: Multiline fields within quotes (HARD)
@@ -324,22 +1410,20 @@ mod tests {
             &["csv_samples/employees1.csv", "csv_samples/employees6.csv"],
             output,
             None,
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            false,
-            false
+            DuplicateHandling::Keep,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
         
         // Verify newlines within quotes are preserved
-        let output_file = File::open(output)?;
-        let output_reader = BufReader::new(output_file);
-        let mut output_lines = output_reader.lines();
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
         let mut row_counter: usize = 0;
         
         // println!("*****Output file contents:");
-        while let Some(_fields) = parse_next_line(&mut output_lines, ',')? {
+        while let Some(_fields) = output_reader.next_record()? {
             row_counter += 1;
             // println!("{:?}", _fields);
         }
@@ -361,10 +1445,10 @@ mod tests {
             &["csv_samples/employees1.csv", "csv_samples/products.csv"],
             output,
             None,
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            false,
-            false
+            DuplicateHandling::Keep,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
@@ -399,23 +1483,21 @@ mod tests {
             &["csv_samples/employees1.csv", "csv_samples/employees7.csv"],
             output,
             None,
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            true,
-            false
+            DuplicateHandling::Remove,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
         
         // Verify duplicate detection/handling
         // employees7 has 3 duplicates from employees1 (IDs 1, 2, 3)
-        let output_file = File::open(output)?;
-        let output_reader = BufReader::new(output_file);
-        let mut output_lines = output_reader.lines();
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
         let mut row_counter: usize = 0;
         
         // println!("*****Output file contents:");
-        while let Some(_fields) = parse_next_line(&mut output_lines, ',')? {
+        while let Some(_fields) = output_reader.next_record()? {
             row_counter += 1;
             // println!("{:?}", _fields);
         }
@@ -442,22 +1524,20 @@ mod tests {
             ],
             output,
             None,
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            false,
-            false
+            DuplicateHandling::Keep,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
         
         // Verify all data is included
-        let output_file = File::open(output)?;
-        let output_reader = BufReader::new(output_file);
-        let mut output_lines = output_reader.lines();
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
         let mut row_counter: usize = 0;
         
         // println!("*****Output file contents:");
-        while let Some(_fields) = parse_next_line(&mut output_lines, ',')? {
+        while let Some(_fields) = output_reader.next_record()? {
             row_counter += 1;
             // println!("{:?}", _fields);
         }
@@ -483,22 +1563,20 @@ mod tests {
             ],
             output,
             Some(&["id"]),
-            ',',
+            &CsvDialect::default(),
             "EMPTY",
-            false,
-            true
+            DuplicateHandling::Merge,
+            RaggedRows::Pad
         );
         
         assert!(result.is_ok(), "Combine should succeed");
         
         // Verify merged rows reconstruct the original data
-        let output_file = File::open(output)?;
-        let output_reader = BufReader::new(output_file);
-        let mut output_lines = output_reader.lines();
+        let mut output_reader = CsvReader::open(output, &CsvDialect::default())?;
         let mut row_counter: usize = 0;
         
         // Check header
-        let header = parse_next_line(&mut output_lines, ',')?.unwrap();
+        let header = output_reader.next_record()?.unwrap();
         assert_eq!(header.len(), 4, "Header should have 4 columns");
         assert!(header.contains(&"id".to_string()), "Header should contain 'id'");
         assert!(header.contains(&"name".to_string()), "Header should contain 'name'");
@@ -506,7 +1584,7 @@ mod tests {
         assert!(header.contains(&"salary".to_string()), "Header should contain 'salary'");
         
         // Count and verify data rows
-        while let Some(fields) = parse_next_line(&mut output_lines, ',')? {
+        while let Some(fields) = output_reader.next_record()? {
             row_counter += 1;
             assert_eq!(fields.len(), 4, "Each row should have 4 fields");
             // Verify no EMPTY values (all should be filled from merge)
@@ -535,4 +1613,302 @@ mod tests {
         let _ = fs::remove_file(output);
         Ok(())
     }
+
+    #[test]
+    fn test_merge_conflicts() -> crate::Result<()> {
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees1_update.csv"];
+        let records = |conflicts| Ok::<_, CombineError>(CombinedRecords::new(&files, Some(&["id"]), &CsvDialect::default(), "", DuplicateHandling::Merge, RaggedRows::Pad)?.conflicts(conflicts));
+
+        let mut merged = records(ConflictHandling::Report)?;
+        assert_eq!(merged.by_ref().count(), 5);
+        let conflicts = &merged.summary().conflicts;
+        assert_eq!(conflicts.len(), 3, "Empty values and new columns should not conflict");
+        assert_eq!(conflicts[0].key, [("id".to_string(), "2".to_string())]);
+        assert_eq!(conflicts[0].column, "department");
+        let sources: Vec<(&str, &str, usize)> = conflicts[0].candidates.iter().map(|c| (c.value.as_str(), c.path.as_str(), c.line)).collect();
+        assert_eq!(sources, [("Marketing", "csv_samples/employees1.csv", 3), ("Sales", "csv_samples/employees1_update.csv", 2)]);
+
+        let mut merged = records(ConflictHandling::Fail)?;
+        let Some(Err(CombineError::Conflict { conflicts, .. })) = merged.next() else {
+            panic!("Conflicts should fail before the first merged row");
+        };
+        assert_eq!(conflicts.len(), 3);
+        assert!(merged.next().is_none());
+
+        let mut merged = records(ConflictHandling::Ignore)?;
+        assert_eq!(merged.by_ref().count(), 5);
+        assert!(merged.summary().conflicts.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn test_missing_key_columns() -> crate::Result<()> {
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees1_name.csv", "csv_samples/employees1_salary.csv"];
+        let records = |keys: &[&str], key_presence| {
+            let inputs = files.iter().map(|&file| Input::from(file)).collect();
+            CombinedRecords::from_inputs(inputs, Some(keys), &CsvDialect::default(), "", DuplicateHandling::Remove, RaggedRows::Pad, key_presence)
+        };
+        let Err(CombineError::MissingKeys { missing }) = records(&["id", "name", "salary"], KeyPresence::Every) else {
+            panic!("Keys missing from some inputs should be an error");
+        };
+        assert_eq!(missing, [
+            ("csv_samples/employees1_name.csv".to_string(), vec!["salary".to_string()]),
+            ("csv_samples/employees1_salary.csv".to_string(), vec!["name".to_string()]),
+        ]);
+        assert_eq!(
+            CombineError::MissingKeys { missing }.to_string(),
+            "key columns missing from input headers: csv_samples/employees1_name.csv lacks salary; csv_samples/employees1_salary.csv lacks name"
+        );
+
+        // With KeyPresence::Any only keys that no input has are an error
+        assert_eq!(records(&["id", "name", "salary"], KeyPresence::Any)?.count(), 15);
+        let Err(CombineError::MissingKeys { missing }) = records(&["id", "email"], KeyPresence::Any) else {
+            panic!("A key in no input should be an error");
+        };
+        assert!(missing.iter().all(|(_, keys)| keys == &["email"]), "Got {:?}", missing);
+
+        // Keys taken from the first header are not checked
+        assert!(CombinedRecords::new(&files, None, &CsvDialect::default(), "", DuplicateHandling::Keep, RaggedRows::Pad).is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_key_normalization() -> crate::Result<()> {
+        let remove = |key: &str, normalization: &str| -> crate::Result<Vec<Record>> {
+            CombinedRecords::new(&["csv_samples/employees12.csv"], Some(&[key]), &CsvDialect::default(), "", DuplicateHandling::Remove, RaggedRows::Pad)?
+                .normalize_keys(normalization.parse().unwrap())
+                .collect()
+        };
+        assert_eq!(remove("id", "none")?.len(), 4, "Only the quoted 42 matches without normalization");
+        assert_eq!(remove("id", "trim,strip-zeros")?.len(), 3);
+        let rows = remove("id", "trim,numeric")?;
+        assert_eq!(rows.len(), 2);
+        assert_eq!(rows[1].get("id"), Some("7"), "Output should keep the value as read");
+        assert_eq!(remove("email", "trim,casefold")?.len(), 2);
+        assert_eq!(remove("name", "none")?.len(), 3);
+        assert_eq!(remove("name", "nfc")?.len(), 2);
+
+        let error = CombinedRecords::new(&["csv_samples/employees12.csv"], Some(&["id"]), &CsvDialect::default(), "", DuplicateHandling::Remove, RaggedRows::Pad)?
+            .normalize_key("email", KeyNormalization::default())
+            .err()
+            .expect("Only key columns can be normalized");
+        assert!(matches!(error, CombineError::Config { .. }), "Got {:?}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_keep_duplicate() -> crate::Result<()> {
+        let departments = |keep: &str| -> crate::Result<Vec<String>> {
+            let records = CombinedRecords::new(&["csv_samples/employees11.csv"], Some(&["id"]), &CsvDialect::default(), "", DuplicateHandling::Remove, RaggedRows::Pad)?
+                .keep_duplicate(keep.parse().unwrap())?;
+            records.map(|record| Ok(record?.get("department").unwrap().to_string())).collect()
+        };
+        assert_eq!(departments("first")?, ["Engineering", "Marketing", "Engineering"]);
+        assert_eq!(departments("last")?, ["Platform", "Sales", "Research"]);
+        assert_eq!(departments("max:updated_at")?, ["Platform", "Marketing", "Research"]);
+        assert_eq!(departments("min:updated_at")?, ["Engineering", "Sales", "Research"], "Empty values should never be the minimum");
+
+        let error = departments("max:hired").expect_err("Unknown keep column should be an error");
+        assert!(matches!(error, CombineError::Config { .. }), "Got {:?}", error);
+        assert!("newest".parse::<KeepDuplicate>().is_err());
+        assert!("max:".parse::<KeepDuplicate>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_join_modes() -> crate::Result<()> {
+        let ids = |files: &[&str], join| -> crate::Result<Vec<String>> {
+            let records = CombinedRecords::new(files, Some(&["id"]), &CsvDialect::default(), "", DuplicateHandling::Merge, RaggedRows::Pad)?.join(join);
+            records.map(|record| Ok(record?.get("id").unwrap().to_string())).collect()
+        };
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees7.csv"];
+        assert_eq!(ids(&files, JoinMode::Inner)?, ["1", "2", "3"]);
+        assert_eq!(ids(&files, JoinMode::Left)?, ["1", "2", "3", "4", "5"]);
+        assert_eq!(ids(&files, JoinMode::Right)?, ["1", "2", "3", "31", "32", "33"], "Rows should stay in first-seen order");
+        assert_eq!(ids(&files, JoinMode::Full)?.len(), 8);
+        assert_eq!(ids(&files, JoinMode::Anti)?, ["4", "5"]);
+        let three_files = ["csv_samples/employees1.csv", "csv_samples/employees7.csv", "csv_samples/employees1_salary.csv"];
+        assert_eq!(ids(&three_files, JoinMode::Inner)?, ["1", "2", "3"], "Inner should need every input");
+        assert_eq!(ids(&three_files, JoinMode::Anti)?, Vec::<String>::new(), "Anti should drop keys in any other input");
+
+        let conflicting_files = ["csv_samples/employees7.csv", "csv_samples/employees1_update.csv"];
+        let records = CombinedRecords::new(&conflicting_files, Some(&["id"]), &CsvDialect::default(), "", DuplicateHandling::Merge, RaggedRows::Pad)?
+            .join(JoinMode::Anti)
+            .conflicts(ConflictHandling::Fail);
+        assert_eq!(records.collect::<crate::Result<Vec<Record>>>()?.len(), 5, "Conflicts in rows that are not written should not count");
+        Ok(())
+    }
+
+    #[test]
+    fn test_set_operations() -> crate::Result<()> {
+        let ids = |files: &[&str], keys: Option<&[&str]>, set_operation| -> crate::Result<Vec<String>> {
+            let records = CombinedRecords::new(files, keys, &CsvDialect::default(), "", DuplicateHandling::Remove, RaggedRows::Pad)?
+                .set_operation(set_operation)
+                .whole_row_keys(keys.is_none());
+            records.map(|record| Ok(record?.get("id").unwrap().to_string())).collect()
+        };
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees7.csv"];
+        assert_eq!(ids(&files, None, SetOperation::Union)?.len(), 8);
+        assert_eq!(ids(&files, None, SetOperation::Intersect)?, ["1", "2", "3"]);
+        assert_eq!(ids(&files, None, SetOperation::Except)?, ["4", "5"]);
+        assert_eq!(ids(&files, None, SetOperation::SymmetricDifference)?, ["4", "5", "31", "32", "33"]);
+
+        // employees1_update.csv has other columns, so none of its rows are whole-row matches
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees1_update.csv"];
+        assert_eq!(ids(&files, None, SetOperation::Intersect)?, Vec::<String>::new());
+        assert_eq!(ids(&files, Some(&["id"]), SetOperation::Intersect)?, ["2", "4"]);
+        assert_eq!(ids(&files, Some(&["id"]), SetOperation::SymmetricDifference)?, ["1", "3", "5"]);
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_dialects() -> crate::Result<()> {
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees15.tsv", "csv_samples/employees16.csv"];
+        let sniffed = CsvDialect { sniff: true, ..CsvDialect::default() };
+        let records = CombinedRecords::new(&files, None, &sniffed, "", DuplicateHandling::Keep, RaggedRows::Error)?;
+        assert_eq!(records.header(), ["id", "name", "department", "salary"], "Every input should be split by its own delimiter");
+        let rows: Vec<Record> = records.collect::<crate::Result<_>>()?;
+        assert_eq!(rows.len(), 9);
+        assert_eq!((rows[7].get("name"), rows[7].get("salary")), (Some("Dubois; Claire"), Some("71000,50")));
+
+        let tsv_only = CsvDialect { file_dialects: vec![("csv_samples/employees15.tsv".to_string(), Delimiter::Char('\t').into())], ..CsvDialect::default() };
+        let rows: Vec<Record> = CombinedRecords::new(&files[..2], None, &tsv_only, "", DuplicateHandling::Keep, RaggedRows::Error)?.collect::<crate::Result<_>>()?;
+        assert_eq!(rows[6].get("name"), Some("Lena Fischer"));
+
+        let headerless = CombinedRecords::new(&["csv_samples/employees17.csv"], None, &sniffed, "", DuplicateHandling::Keep, RaggedRows::Error)?;
+        assert_eq!(headerless.header(), ["column_1", "column_2", "column_3", "column_4"]);
+        assert_eq!(headerless.count(), 2, "The first row should be data");
+
+        let converted = CsvDialect { delimiter: ';', output_delimiter: Some('|'), output_quote: '\'', ..CsvDialect::default() };
+        let mut buffer: Vec<u8> = Vec::new();
+        combine_files_into(&files[2..], &mut buffer, None, &converted, "", DuplicateHandling::Keep, RaggedRows::Error)?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "id|name|department|salary\n44|Dubois; Claire|Finance|71000,50\n45|Marco Rossi|Marketing|69000\n",
+            "The output should only quote for its own delimiter");
+
+        let unknown = CsvDialect { file_dialects: vec![("missing.csv".to_string(), Delimiter::Auto.into())], ..CsvDialect::default() };
+        let error = CombinedRecords::new(&files, None, &unknown, "", DuplicateHandling::Keep, RaggedRows::Pad).err().expect("Delimiters should be for inputs");
+        assert!(matches!(error, CombineError::Config { .. }), "Got {:?}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_encodings() -> crate::Result<()> {
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees18.csv", "csv_samples/employees19.csv"];
+        let rows: Vec<Record> = CombinedRecords::new(&files, None, &CsvDialect::default(), "", DuplicateHandling::Keep, RaggedRows::Error)?.collect::<crate::Result<_>>()?;
+        let names: Vec<&str> = rows[5..].iter().map(|row| row.get("name").unwrap()).collect();
+        assert_eq!(names, ["Ren\u{e9} M\u{fc}ller", "Zo\u{eb} \u{d8}deg\u{e5}rd", "Jos\u{e9} Garc\u{ed}a", "Ana\u{ef}s Lef\u{e8}vre"], "Windows-1252 and UTF-16 should be detected");
+
+        //a reader input is buffered as UTF-8 when spilling
+        let utf16: Vec<u8> = [0xFF, 0xFE].into_iter().chain("id,name\n50,Bj\u{f6}rn,x\n".encode_utf16().flat_map(|unit| unit.to_le_bytes())).collect();
+        let inputs = vec![Input::reader("<utf16>", Cursor::new(utf16))];
+        let rows: Vec<Record> = CombinedRecords::from_inputs(inputs, None, &CsvDialect::default(), "", DuplicateHandling::Keep, RaggedRows::Spill, KeyPresence::Every)?.collect::<crate::Result<_>>()?;
+        assert_eq!(rows[0].fields(), ["50", "Bj\u{f6}rn", "x"]);
+
+        let utf8_only = CsvDialect { file_encodings: vec![(files[1].to_string(), TextEncoding::Named(UTF_8))], ..CsvDialect::default() };
+        let error = CombinedRecords::new(&files, None, &utf8_only, "", DuplicateHandling::Keep, RaggedRows::Error)?.collect::<crate::Result<Vec<Record>>>().expect_err("Windows-1252 is not UTF-8");
+        assert!(matches!(&error, CombineError::Io { path, .. } if path == files[1]), "Got {:?}", error);
+
+        let latin1 = CsvDialect { output_encoding: encoding_rs::WINDOWS_1252, ..CsvDialect::default() };
+        let mut buffer: Vec<u8> = Vec::new();
+        combine_files_into(&files[1..2], &mut buffer, None, &latin1, "", DuplicateHandling::Keep, RaggedRows::Error)?;
+        assert_eq!(buffer, fs::read(files[1]).unwrap(), "Windows-1252 should come back out unchanged");
+        let inputs = vec![Input::reader("<memory>", "id,name\n51,\u{141}ucja\n".as_bytes())];
+        let error = write_records(CombinedRecords::from_inputs(inputs, None, &latin1, "", DuplicateHandling::Keep, RaggedRows::Error, KeyPresence::Every)?, Vec::new(), &latin1, "out.csv")
+            .expect_err("\u{141} is not in Windows-1252");
+        assert!(matches!(error, CombineError::Io { .. }), "Got {:?}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_delimiter_forms() {
+        for (value, expected) in [("\\t", '\t'), ("tab", '\t'), ("pipe", '|'), ("semicolon", ';'), ("\\x1f", '\u{1f}'), (";", ';'), ("\\", '\\')] {
+            assert_eq!(value.parse::<Delimiter>(), Ok(Delimiter::Char(expected)), "Unexpected delimiter for {:?}", value);
+        }
+        assert_eq!("auto".parse::<Delimiter>(), Ok(Delimiter::Auto));
+        for value in ["abc", "\\x1", "\\xzz", "\\n", "\n", "\\x0d"] {
+            assert!(value.parse::<Delimiter>().is_err(), "{:?} should not be a delimiter", value);
+        }
+        assert!(Delimiter::parse_char("auto").is_err(), "auto is not a character");
+        assert_eq!("tab,',noheader".parse::<FileDialect>(), Ok(FileDialect { delimiter: Delimiter::Char('\t'), quote: Some('\''), has_header: Some(false) }));
+        assert_eq!("auto,header".parse::<FileDialect>(), Ok(FileDialect { delimiter: Delimiter::Auto, quote: None, has_header: Some(true) }));
+        for value in ["", ",", "tab,'',", "tab,noheader,'"] {
+            assert!(value.parse::<FileDialect>().is_err(), "{:?} should not be a dialect", value);
+        }
+
+        let quoted_delimiter = CsvDialect { delimiter: '\'', quote: '\'', ..CsvDialect::default() };
+        let error = CombinedRecords::new(&["csv_samples/employees1.csv"], None, &quoted_delimiter, "", DuplicateHandling::Keep, RaggedRows::Pad).err().expect("The delimiter should not be the quote");
+        assert_eq!(error.to_string(), "delimiter '\\'' is also the quote character");
+        assert!(CsvDialect { output_delimiter: Some('"'), ..CsvDialect::default() }.validate().is_err(), "The output should be checked on its own");
+        assert!(CsvDialect { delimiter: '\'', output_delimiter: Some(','), quote: '"', output_quote: '\'', ..CsvDialect::default() }.validate().is_ok());
+        let file_delimiter = CsvDialect { file_dialects: vec![("a.csv".to_string(), Delimiter::Char('"').into())], ..CsvDialect::default() };
+        assert!(file_delimiter.validate().is_err());
+        let file_quote = CsvDialect { file_dialects: vec![("a.csv".to_string(), "tab,\t".parse().unwrap())], ..CsvDialect::default() };
+        assert!(file_quote.validate().is_err(), "A file's delimiter should be checked against its own quote");
+    }
+
+    #[test]
+    fn test_fuzzy_keys() -> crate::Result<()> {
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees13.csv"];
+        let records = |duplicates, fuzzy_keys: &str| -> crate::Result<CombinedRecords> {
+            Ok(CombinedRecords::new(&files, Some(&["name"]), &CsvDialect::default(), "", duplicates, RaggedRows::Pad)?.fuzzy_keys(fuzzy_keys.parse().unwrap()))
+        };
+
+        let mut merged = records(DuplicateHandling::Merge, "levenshtein:1")?;
+        let rows: Vec<Record> = merged.by_ref().collect::<crate::Result<_>>()?;
+        assert_eq!(rows.len(), 7, "Alice, Bob and Carol should merge, Dave and Frank are new");
+        assert_eq!(rows[0].get("name"), Some("Alice Johnson"), "The first row's key should be kept");
+        assert_eq!(rows[0].get("phone"), Some("555-0101"));
+        let matches = &merged.summary().fuzzy_matches;
+        assert_eq!(matches.len(), 2, "Exact matches should not be reported");
+        assert_eq!(matches[1].key, [("name".to_string(), "Bob Smith".to_string())]);
+        assert_eq!((matches[1].matched[0].as_str(), matches[1].line, matches[1].score), ("Bob Smyth", 3, 1.0));
+
+        let rows: Vec<Record> = records(DuplicateHandling::Remove, "levenshtein:2")?.collect::<crate::Result<_>>()?;
+        assert_eq!(rows.len(), 6, "Only Frank should be added");
+        assert_eq!(rows[3].get("name"), Some("David Brown"), "The first row should be kept");
+        assert_eq!(rows[3].get("email"), Some(""));
+        Ok(())
+    }
+
+    #[test]
+    fn test_input_priority() -> crate::Result<()> {
+        let files = ["csv_samples/employees1.csv", "csv_samples/employees1_update.csv"];
+        let records = |duplicates| CombinedRecords::new(&files, Some(&["id"]), &CsvDialect::default(), "", duplicates, RaggedRows::Pad)?.priority("csv_samples/employees1_update.csv", 10);
+
+        // The whole row comes from the higher-priority file, in the place its key was first seen
+        let rows: Vec<Record> = records(DuplicateHandling::Remove)?.collect::<crate::Result<_>>()?;
+        assert_eq!(rows.len(), 5);
+        assert_eq!(rows[1].fields(), ["2", "", "Sales", "70000", "moved teams"]);
+        assert_eq!(rows[3].fields(), ["4", "", "", "69000", ""]);
+
+        // Only its non-empty values win when merging
+        let rows: Vec<Record> = records(DuplicateHandling::Merge)?.collect::<crate::Result<_>>()?;
+        assert_eq!(rows[1].fields(), ["2", "Bob Smith", "Sales", "70000", "moved teams"]);
+        assert_eq!(rows[3].fields(), ["4", "David Brown", "Sales", "69000", ""]);
+
+        let error = records(DuplicateHandling::Merge)?.priority("hr.csv", 1).err().expect("Unknown input should be an error");
+        assert!(matches!(error, CombineError::Config { .. }), "Got {:?}", error);
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_order_and_sort() -> crate::Result<()> {
+        let files = ["csv_samples/employees2.csv", "csv_samples/employees1_name.csv", "csv_samples/employees1_salary.csv"];
+        let ids = |records: CombinedRecords| -> crate::Result<Vec<String>> {
+            records.map(|record| Ok(record?.fields()[0].clone())).collect()
+        };
+        // Merged rows come out in the order their key was first seen, every run
+        let records = CombinedRecords::new(&files, Some(&["id"]), &CsvDialect::default(), "", DuplicateHandling::Merge, RaggedRows::Pad)?;
+        assert_eq!(ids(records)?, ["6", "7", "8", "9", "10", "1", "2", "3", "4", "5"]);
+
+        // Numeric values sort by value, ties keep their input order
+        let records = CombinedRecords::new(&files, Some(&["id"]), &CsvDialect::default(), "", DuplicateHandling::Merge, RaggedRows::Pad)?.sort_by(&["id"])?;
+        assert_eq!(ids(records)?, ["1", "2", "3", "4", "5", "6", "7", "8", "9", "10"]);
+        let records = CombinedRecords::new(&["csv_samples/employees1.csv", "csv_samples/employees2.csv"], None, &CsvDialect::default(), "", DuplicateHandling::Keep, RaggedRows::Pad)?.sort_by(&["department"])?;
+        assert_eq!(ids(records)?, ["1", "3", "6", "9", "5", "10", "2", "7", "4", "8"]);
+
+        let error = CombinedRecords::new(&files, None, &CsvDialect::default(), "", DuplicateHandling::Keep, RaggedRows::Pad)?.sort_by(&["age"]).err().expect("Unknown sort column should be an error");
+        assert!(matches!(error, CombineError::Config { .. }), "Got {:?}", error);
+        Ok(())
+    }
 }
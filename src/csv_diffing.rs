@@ -0,0 +1,229 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::io::{self, Write};
+
+use crate::combine_error::{CombineError, Result};
+use crate::conflict_report::{json_string, ReportFormat};
+use crate::csv_combining::{CombinedRecords, CombineSummary, CsvDialect};
+use crate::csv_writing::CsvWriter;
+use crate::text_encoding::EncodingWriter;
+
+/// How a row differs between the old and the new snapshot
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ChangeType {
+    /// Only in the new snapshot
+    Added,
+    /// Only in the old snapshot
+    Removed,
+    /// In both, with different values in a column both snapshots have
+    Changed,
+}
+
+impl fmt::Display for ChangeType {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ChangeType::Added => write!(f, "added"),
+            ChangeType::Removed => write!(f, "removed"),
+            ChangeType::Changed => write!(f, "changed"),
+        }
+    }
+}
+
+/// One column's value before and after.  `None` when the row is not in that snapshot
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ColumnChange {
+    pub column: String,
+    pub old: Option<String>,
+    pub new: Option<String>,
+}
+
+/// A row that was added, removed or changed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RowChange {
+    pub change_type: ChangeType,
+    /// Key column names and values, as read from the new row (the old one when removed)
+    pub key: Vec<(String, String)>,
+    /// Every non-key column of an added or removed row, only the differing ones of a changed row
+    pub columns: Vec<ColumnChange>,
+}
+
+/// Every row that differs between two snapshots: removed and changed rows in the old file's order, then added rows
+#[derive(Debug, Default)]
+pub struct SnapshotDiff {
+    pub changes: Vec<RowChange>,
+    /// Key column names, warnings and ragged row counts from reading the snapshots
+    pub summary: CombineSummary,
+}
+
+impl SnapshotDiff {
+    pub fn count(&self, change_type: ChangeType) -> usize {
+        self.changes.iter().filter(|change| change.change_type == change_type).count()
+    }
+}
+
+struct Snapshot {
+    rows: Vec<Vec<String>>,
+    indexes: HashMap<Vec<String>, usize>, //match key to position in rows
+    has_column: Vec<bool>, //by output column
+}
+
+/// Compares the rows of a two-input `CombinedRecords`, old file first.  Rows are matched by key, after any
+/// normalization, and each key has to be unique within its file.  Only columns both files have can change
+pub fn diff_records(mut records: CombinedRecords) -> Result<SnapshotDiff> {
+    let header = records.header().to_vec();
+    let key_len = records.key_len();
+    let mut snapshots: Vec<Snapshot> = (0..2).map(|file_index| {
+        let mut has_column = vec![false; header.len()];
+        for &i in records.input_columns(file_index) {
+            has_column[i] = true;
+        }
+        Snapshot { rows: Vec::new(), indexes: HashMap::new(), has_column }
+    }).collect();
+    while let Some(record) = records.next() {
+        let fields = record?.into_fields();
+        let (file_index, line) = records.position();
+        let key = records.match_key(&fields);
+        let snapshot = &mut snapshots[file_index];
+        if snapshot.indexes.insert(key, snapshot.rows.len()).is_some() {
+            let message = "key appears more than once, rows can only be compared by a unique key".to_string();
+            return Err(CombineError::Key { path: records.filename(file_index).to_string(), line, message });
+        }
+        snapshot.rows.push(fields);
+    }
+
+    let [old, new] = [&snapshots[0], &snapshots[1]];
+    let key = |fields: &[String]| -> Vec<(String, String)> { header[..key_len].iter().cloned().zip(fields[..key_len].iter().cloned()).collect() };
+    let mut changes: Vec<RowChange> = Vec::new();
+    for (match_key, &old_index) in sorted_by_position(&old.indexes) {
+        let old_fields = &old.rows[old_index];
+        let Some(&new_index) = new.indexes.get(match_key) else {
+            let columns = (key_len..header.len()).filter(|&i| old.has_column[i])
+                .map(|i| ColumnChange { column: header[i].clone(), old: Some(old_fields[i].clone()), new: None })
+                .collect();
+            changes.push(RowChange { change_type: ChangeType::Removed, key: key(old_fields), columns });
+            continue;
+        };
+        let new_fields = &new.rows[new_index];
+        let columns: Vec<ColumnChange> = (key_len..header.len())
+            .filter(|&i| old.has_column[i] && new.has_column[i] && old_fields[i] != new_fields[i])
+            .map(|i| ColumnChange { column: header[i].clone(), old: Some(old_fields[i].clone()), new: Some(new_fields[i].clone()) })
+            .collect();
+        if !columns.is_empty() {
+            changes.push(RowChange { change_type: ChangeType::Changed, key: key(new_fields), columns });
+        }
+    }
+    for (match_key, &new_index) in sorted_by_position(&new.indexes) {
+        if old.indexes.contains_key(match_key) {
+            continue;
+        }
+        let new_fields = &new.rows[new_index];
+        let columns = (key_len..header.len()).filter(|&i| new.has_column[i])
+            .map(|i| ColumnChange { column: header[i].clone(), old: None, new: Some(new_fields[i].clone()) })
+            .collect();
+        changes.push(RowChange { change_type: ChangeType::Added, key: key(new_fields), columns });
+    }
+    Ok(SnapshotDiff { changes, summary: records.into_summary() })
+}
+
+fn sorted_by_position(indexes: &HashMap<Vec<String>, usize>) -> Vec<(&Vec<String>, &usize)> {
+    let mut sorted: Vec<(&Vec<String>, &usize)> = indexes.iter().collect();
+    sorted.sort_by_key(|&(_, &index)| index);
+    sorted
+}
+
+fn write_csv<W: Write>(diff: &SnapshotDiff, writer: W, dialect: &CsvDialect) -> io::Result<()> {
+    let mut csv_writer = CsvWriter::new(writer, dialect.output_delimiter(), dialect.quote_style).quote(dialect.output_quote);
+    let mut header = vec!["change".to_string()];
+    header.extend(diff.summary.key_columns.iter().cloned()); //from the summary, so an empty diff has them too
+    header.extend(["column", "old", "new"].map(String::from));
+    csv_writer.write_record(&header)?;
+    for change in &diff.changes {
+        let mut row = vec![change.change_type.to_string()];
+        row.extend(change.key.iter().map(|(_, value)| value.clone()));
+        if change.columns.is_empty() {
+            row.extend([String::new(), String::new(), String::new()]); //a row with nothing but key columns
+            csv_writer.write_record(&row)?;
+            continue;
+        }
+        let key_end = row.len();
+        for column in &change.columns {
+            row.truncate(key_end);
+            row.extend([column.column.clone(), column.old.clone().unwrap_or_default(), column.new.clone().unwrap_or_default()]);
+            csv_writer.write_record(&row)?;
+        }
+    }
+    csv_writer.flush()
+}
+
+fn write_json<W: Write>(diff: &SnapshotDiff, mut writer: W) -> io::Result<()> {
+    let json_value = |value: &Option<String>| value.as_deref().map_or("null".to_string(), json_string);
+    writeln!(writer, "[")?;
+    for (i, change) in diff.changes.iter().enumerate() {
+        let key: Vec<String> = change.key.iter().map(|(column, value)| format!("{}: {}", json_string(column), json_string(value))).collect();
+        let columns: Vec<String> = change.columns.iter()
+            .map(|column| format!("{}: {{\"old\": {}, \"new\": {}}}", json_string(&column.column), json_value(&column.old), json_value(&column.new)))
+            .collect();
+        let comma = if i + 1 < diff.changes.len() { "," } else { "" };
+        writeln!(writer, "  {{\"change\": \"{}\", \"key\": {{{}}}, \"columns\": {{{}}}}}{}", change.change_type, key.join(", "), columns.join(", "), comma)?;
+    }
+    writeln!(writer, "]")?;
+    writer.flush()
+}
+
+/// Writes a diff as CSV, one row per changed column with a change-type column, or as a JSON array with one object per row.
+/// Both use the dialect's output encoding, CSV also its output delimiter, quote character and quote style
+pub fn write_diff<W: Write>(diff: &SnapshotDiff, writer: W, format: ReportFormat, dialect: &CsvDialect) -> io::Result<()> {
+    let writer = EncodingWriter::new(writer, dialect.output_encoding);
+    match format {
+        ReportFormat::Csv => write_csv(diff, writer, dialect),
+        ReportFormat::Json => write_json(diff, writer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::csv_combining::{DuplicateHandling, RaggedRows};
+
+    fn diff(files: &[&str]) -> Result<SnapshotDiff> {
+        diff_records(CombinedRecords::new(files, Some(&["id"]), &CsvDialect::default(), "", DuplicateHandling::Keep, RaggedRows::Pad)?)
+    }
+
+    #[test]
+    fn test_diff_snapshots() -> io::Result<()> {
+        let diff = diff(&["csv_samples/employees1.csv", "csv_samples/employees14.csv"])?;
+        let summary: Vec<(ChangeType, &str)> = diff.changes.iter().map(|change| (change.change_type, change.key[0].1.as_str())).collect();
+        assert_eq!(summary, [(ChangeType::Changed, "2"), (ChangeType::Changed, "3"), (ChangeType::Removed, "4"), (ChangeType::Added, "6")]);
+        assert_eq!(diff.changes[1].columns, [ColumnChange { column: "salary".to_string(), old: Some("88000".to_string()), new: Some("91000".to_string()) }],
+            "A column only in the new file should not count as changed");
+        assert_eq!(diff.changes[3].columns.len(), 4, "Added rows should list every column they have");
+
+        let mut buffer: Vec<u8> = Vec::new();
+        write_diff(&diff, &mut buffer, ReportFormat::Csv, &CsvDialect::default())?;
+        let csv = String::from_utf8(buffer).unwrap();
+        assert_eq!(csv.lines().take(3).collect::<Vec<_>>(), ["change,id,column,old,new", "changed,2,department,Marketing,Sales", "changed,3,salary,88000,91000"]);
+
+        let mut buffer: Vec<u8> = Vec::new();
+        write_diff(&diff, &mut buffer, ReportFormat::Json, &CsvDialect::default())?;
+        let json = String::from_utf8(buffer).unwrap();
+        assert!(json.contains(r#"{"change": "removed", "key": {"id": "4"}, "columns": {"name": {"old": "David Brown", "new": null}, "#), "Got: {}", json);
+        Ok(())
+    }
+
+    #[test]
+    fn test_empty_diff() -> io::Result<()> {
+        let diff = diff(&["csv_samples/employees1.csv", "csv_samples/employees1.csv"])?;
+        assert!(diff.changes.is_empty());
+        let mut buffer: Vec<u8> = Vec::new();
+        write_diff(&diff, &mut buffer, ReportFormat::Csv, &CsvDialect::default())?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "change,id,column,old,new\n", "An empty diff should still have the key columns");
+        Ok(())
+    }
+
+    #[test]
+    fn test_diff_duplicate_key() {
+        assert!(diff(&["csv_samples/employees7.csv", "csv_samples/employees1_update.csv"]).is_ok(), "Unique keys should diff");
+        let error = diff(&["csv_samples/employees1.csv", "csv_samples/employees11.csv"]).expect_err("Repeated keys should fail");
+        assert!(matches!(&error, CombineError::Key { path, line: 4, .. } if path == "csv_samples/employees11.csv"), "Got {:?}", error);
+    }
+}
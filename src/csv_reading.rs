@@ -0,0 +1,301 @@
+use std::error::Error;
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufRead, BufReader, Lines};
+use std::mem;
+
+use clap::ValueEnum;
+
+use crate::combine_error::{CombineError, IoResultExt, Result};
+use crate::csv_combining::CsvDialect;
+
+// Define line ending based on OS https://stackoverflow.com/questions/47541191/how-to-get-current-platform-end-of-line-character-sequence-in-rust
+#[cfg(windows)]
+const LINE_ENDING: &str = "\r\n";
+#[cfg(not(windows))]
+const LINE_ENDING: &str = "\n";
+
+/// Which input fields get leading and trailing whitespace removed
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum Trim {
+    /// Keep all whitespace
+    #[default]
+    None,
+    /// Trim unquoted fields and padding outside the quotes, keep whitespace inside quotes
+    Unquoted,
+    /// Trim every field, including whitespace inside quotes
+    All,
+}
+
+/// Malformed input that stops a file from being read, with the position the problem starts at
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ParseError {
+    pub filename: String,
+    /// 1-based line the offending record starts on
+    pub line: usize,
+    /// 1-based character column within that line
+    pub column: usize,
+    pub message: String,
+}
+
+impl fmt::Display for ParseError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}:{}:{}: {}", self.filename, self.line, self.column, self.message)
+    }
+}
+
+impl Error for ParseError {}
+
+fn finish_field(mut field: String, quoted: bool, closed_len: usize, trim: Trim) -> String {
+    //drops whitespace sitting outside the quotes when trimming.  whitespace inside quotes is only removed by Trim::All, once the whole (possibly multiline) field has been read
+    match (trim, quoted) {
+        (Trim::None, _) => field,
+        (_, false) => field.trim().to_string(),
+        (_, true) => {
+            if field[closed_len..].trim().is_empty() {
+                field.truncate(closed_len);
+            }
+            field
+        }
+    }
+}
+
+pub(crate) fn parse_line(line: &str, delimiter: char, quote: char, trim: Trim, in_quotes: bool) -> (Vec<String>, Option<usize>) {
+    //parses a single line into fields.  if the line ends inside a quoted field, also returns the column of that field's opening quote (0 if it opened on an earlier line)
+    //fields are decoded per RFC 4180: surrounding quotes are dropped and doubled quotes ("") become a single quote
+    let mut fields: Vec<String> = Vec::new();
+    let mut current_field = String::new();
+    let mut in_quotes = in_quotes;
+    let mut field_quoted = in_quotes; //a continuation line starts inside the quoted field from the previous line
+    let mut closed_len: usize = 0; //length of the field when its closing quote was read.  anything after that is outside the quotes
+    let mut quote_column: usize = 0;
+    let mut chars = line.chars().enumerate().peekable();
+    while let Some((index, char)) = chars.next() {
+        if in_quotes {
+            if char == quote {
+                if chars.peek().map(|&(_, next)| next) == Some(quote) { //escaped quote
+                    current_field.push(quote);
+                    chars.next();
+                } else { //closing quote
+                    in_quotes = false;
+                    closed_len = current_field.len();
+                }
+            } else {
+                current_field.push(char);
+            }
+        } else if char == delimiter {
+            fields.push(finish_field(mem::take(&mut current_field), field_quoted, closed_len, trim));
+            field_quoted = false;
+        } else if char == quote && !field_quoted && (current_field.is_empty() || (trim != Trim::None && current_field.trim().is_empty())) {
+            //opening quote only counts at the start of a field (or after padding when trimming), stray quotes elsewhere are kept as-is
+            current_field.clear();
+            in_quotes = true;
+            field_quoted = true;
+            quote_column = index + 1;
+        } else {
+            current_field.push(char);
+        }
+    }
+    if in_quotes { //field continues on the next line, so it is not finished yet
+        fields.push(current_field);
+    } else {
+        fields.push(finish_field(current_field, field_quoted, closed_len, trim));
+    }
+    (fields, in_quotes.then_some(quote_column))//concatenation of multiline fields is left to the caller
+}
+
+/// Opens an input file, telling a missing file apart from other I/O errors
+pub fn open_file(filename: &str) -> Result<BufReader<File>> {
+    match File::open(filename) {
+        Ok(file) => Ok(BufReader::new(file)),
+        Err(e) if e.kind() == io::ErrorKind::NotFound => Err(CombineError::InputNotFound { path: filename.to_string() }),
+        Err(e) => Err(CombineError::io(filename, e)),
+    }
+}
+
+/// Reads records from a delimited file or any other buffered reader, joining quoted fields that span several lines
+pub struct CsvReader<R: BufRead = BufReader<File>> {
+    lines: Lines<R>,
+    filename: String,
+    dialect: CsvDialect,
+    line_number: usize, //physical lines read so far
+    record_line: usize, //line the last record returned started on
+    unread: Option<(Vec<String>, usize)>, //record handed back with unread_record, and its line
+    warnings: Vec<String>,
+}
+
+impl CsvReader {
+    pub fn open(filename: &str, dialect: &CsvDialect) -> Result<Self> {
+        Ok(CsvReader::from_reader(open_file(filename)?, filename, dialect))
+    }
+}
+
+impl<R: BufRead> CsvReader<R> {
+    /// Reads from an already open source.  `name` stands in for the file name in errors and warnings
+    pub fn from_reader(reader: R, name: &str, dialect: &CsvDialect) -> Self {
+        CsvReader {
+            lines: reader.lines(),
+            filename: name.to_string(),
+            dialect: dialect.clone(),
+            line_number: 0,
+            record_line: 0,
+            unread: None,
+            warnings: Vec::new(),
+        }
+    }
+
+    fn next_line(&mut self) -> Result<Option<String>> {
+        match self.lines.next() {
+            None => Ok(None),
+            Some(line) => {
+                self.line_number += 1;
+                line.map(Some).with_path(&self.filename)
+            }
+        }
+    }
+
+    /// Reads the next record, or `None` at end of file
+    pub fn next_record(&mut self) -> Result<Option<Vec<String>>> {
+        //reads as many lines as needed to close any multiline quoted field
+        if let Some((record, line)) = self.unread.take() {
+            self.record_line = line;
+            return Ok(Some(record));
+        }
+        let line: String = match self.next_line()? {
+            None => return Ok(None),
+            Some(line) => line,
+        };
+        self.record_line = self.line_number;
+        let delimiter = self.dialect.delimiter;
+        let quote = self.dialect.quote;
+        let trim = self.dialect.trim;
+        let (mut fields, mut open_quote) = parse_line(&line, delimiter, quote, trim, false);
+        let quote_column = open_quote.unwrap_or(0);
+        while open_quote.is_some() {//read next line, then combine last and first fields.  if still in quotes, repeat
+            let next_line = match self.next_line()? {
+                Some(next_line) => next_line,
+                None => {
+                    let error = ParseError {
+                        filename: self.filename.clone(),
+                        line: self.record_line,
+                        column: quote_column,
+                        message: "unterminated quoted field at end of file".to_string(),
+                    };
+                    if !self.dialect.lenient {
+                        return Err(error.into());
+                    }
+                    self.warnings.push(format!("{} (closed at end of file)", error));
+                    break;
+                }
+            };
+            let (next_fields, still_open) = parse_line(&next_line, delimiter, quote, trim, true);
+            let mut last_field = fields.pop().unwrap();
+            last_field.push_str(LINE_ENDING);
+            last_field.push_str(&next_fields[0]);
+            fields.push(last_field);
+            fields.extend_from_slice(&next_fields[1..]);
+            open_quote = still_open;
+        }
+        if trim == Trim::All { //quoted fields are only complete now, so they get trimmed here
+            for field in fields.iter_mut() {
+                let trimmed = field.trim();
+                if trimmed.len() != field.len() {
+                    *field = trimmed.to_string();
+                }
+            }
+        }
+        Ok(Some(fields))
+    }
+
+    /// Hands the last record back, so the next call to `next_record` returns it again, e.g. a first row that is not a header
+    pub fn unread_record(&mut self, record: Vec<String>) {
+        self.unread = Some((record, self.record_line));
+    }
+
+    /// 1-based line the last record returned by `next_record` started on
+    pub fn record_line(&self) -> usize {
+        self.record_line
+    }
+
+    /// Recoverable problems found so far, e.g. quoted fields closed at end of file in lenient mode
+    pub fn take_warnings(&mut self) -> Vec<String> {
+        mem::take(&mut self.warnings)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quoted_field_decoding() {
+        // Quotes are syntax, not data: "Smith" and Smith are the same value and "" decodes to "
+        let (fields, open_quote) = parse_line(r#"24,"Smith, Jr., Robert",99000,"321 Elm St, Building ""A""""#, ',', '"', Trim::None, false);
+        assert!(open_quote.is_none(), "Line should end outside quotes");
+        assert_eq!(fields, vec!["24", "Smith, Jr., Robert", "99000", r#"321 Elm St, Building "A""#]);
+        let (fields, _) = parse_line(r#""Smith",Smith"#, ',', '"', Trim::None, false);
+        assert_eq!(fields[0], fields[1], "Quoted and unquoted values should decode the same");
+    }
+
+    #[test]
+    fn test_trim_modes() {
+        let line = r#"  1 ,  "  Smith, Jr.  "  ,x  "#;
+        let (fields, _) = parse_line(line, ',', '"', Trim::None, false);
+        assert_eq!(fields, vec!["  1 ", r#"  "  Smith"#, r#" Jr.  "  "#, "x  "], "Trim::None should keep everything, padding before a quote makes it literal");
+        let (fields, _) = parse_line(line, ',', '"', Trim::Unquoted, false);
+        assert_eq!(fields, vec!["1", "  Smith, Jr.  ", "x"], "Trim::Unquoted should keep whitespace inside quotes");
+        let (fields, _) = parse_line(line, ',', '"', Trim::All, false);
+        assert_eq!(fields, vec!["1", "  Smith, Jr.  ", "x"], "parse_line leaves quoted content to next_record");
+
+        // Continuation lines of a multiline field keep their indentation
+        let (fields, open_quote) = parse_line(r#"3,"Handles benefits"#, ',', '"', Trim::Unquoted, false);
+        assert_eq!(fields, vec!["3", "Handles benefits"]);
+        assert_eq!(open_quote, Some(3), "Field should continue on the next line");
+        let (fields, open_quote) = parse_line(r#"    - Onboarding  ",  HR"#, ',', '"', Trim::Unquoted, true);
+        assert_eq!(fields, vec!["    - Onboarding  ", "HR"]);
+        assert!(open_quote.is_none());
+    }
+
+    #[test]
+    fn test_missing_input() {
+        let error = CsvReader::open("csv_samples/missing.csv", &CsvDialect::default()).err().expect("Missing file should be an error");
+        assert!(matches!(error, CombineError::InputNotFound { .. }));
+        assert_eq!(error.to_string(), "Input file 'csv_samples/missing.csv' not found");
+    }
+
+    #[test]
+    fn test_from_reader() -> Result<()> {
+        let data = "id,notes\n1,\"two\nlines\"\n2,plain\n";
+        let mut reader = CsvReader::from_reader(data.as_bytes(), "<memory>", &CsvDialect::default());
+        assert_eq!(reader.next_record()?.unwrap(), ["id", "notes"]);
+        assert_eq!(reader.next_record()?.unwrap(), ["1", format!("two{}lines", LINE_ENDING).as_str()]);
+        assert_eq!(reader.next_record()?.unwrap(), ["2", "plain"]);
+        assert_eq!(reader.record_line(), 4, "Line numbers should count the multiline record");
+        assert!(reader.next_record()?.is_none());
+        Ok(())
+    }
+
+    #[test]
+    fn test_unterminated_quote_at_eof() -> Result<()> {
+        // Strict mode reports where the unterminated field starts instead of panicking
+        let mut reader = CsvReader::open("csv_samples/employees8.csv", &CsvDialect::default())?;
+        assert_eq!(reader.next_record()?.unwrap()[0], "id");
+        assert_eq!(reader.next_record()?.unwrap()[0], "34");
+        let Err(CombineError::Parse(parse_error)) = reader.next_record() else {
+            panic!("Unterminated quote should be a parse error");
+        };
+        assert_eq!((parse_error.filename.as_str(), parse_error.line, parse_error.column), ("csv_samples/employees8.csv", 3, 34));
+
+        // Lenient mode closes the field at end of file and records a warning
+        let dialect = CsvDialect { lenient: true, ..CsvDialect::default() };
+        let mut reader = CsvReader::open("csv_samples/employees8.csv", &dialect)?;
+        reader.next_record()?;
+        reader.next_record()?;
+        let fields = reader.next_record()?.expect("Last record should be returned");
+        assert_eq!(fields[4], format!("Export was cut off{}mid-note", LINE_ENDING));
+        assert_eq!(reader.record_line(), 3);
+        assert!(reader.next_record()?.is_none());
+        assert_eq!(reader.take_warnings().len(), 1, "Closing the field should be reported");
+        Ok(())
+    }
+}
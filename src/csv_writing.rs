@@ -0,0 +1,131 @@
+use std::io::{Result, Write};
+
+use clap::ValueEnum;
+
+/// When output fields get wrapped in quotes
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, ValueEnum)]
+pub enum QuoteStyle {
+    /// Only quote fields containing the delimiter, a quote or a line break
+    #[default]
+    Necessary,
+    /// Quote every field
+    Always,
+    /// Quote every field that is not a number
+    NonNumeric,
+    /// Never quote (output may not be re-readable if fields contain special characters)
+    Never,
+}
+
+fn needs_quotes(field: &str, delimiter: char, quote: char) -> bool {
+    field.contains([delimiter, quote, '\r', '\n'])
+}
+
+fn is_numeric(field: &str) -> bool {
+    //plain decimal numbers only: optional sign, digits with at most one '.', optional exponent.  no inf/nan
+    let field = field.strip_prefix(['+', '-']).unwrap_or(field);
+    let (mantissa, exponent) = match field.split_once(['e', 'E']) {
+        Some((mantissa, exponent)) => (mantissa, Some(exponent.strip_prefix(['+', '-']).unwrap_or(exponent))),
+        None => (field, None),
+    };
+    let mut digits = 0;
+    let mut dots = 0;
+    for char in mantissa.chars() {
+        match char {
+            '0'..='9' => digits += 1,
+            '.' => dots += 1,
+            _ => return false,
+        }
+    }
+    let exponent_ok = exponent.is_none_or(|e| !e.is_empty() && e.chars().all(|c| c.is_ascii_digit()));
+    digits > 0 && dots <= 1 && exponent_ok
+}
+
+pub fn encode_field(field: &str, delimiter: char, quote: char, quote_style: QuoteStyle) -> String {
+    //inverse of parse_line: quotes the field according to the quote style, doubling any embedded quotes
+    let quoted = match quote_style {
+        QuoteStyle::Necessary => needs_quotes(field, delimiter, quote),
+        QuoteStyle::Always => true,
+        QuoteStyle::NonNumeric => !is_numeric(field) || needs_quotes(field, delimiter, quote),
+        QuoteStyle::Never => false,
+    };
+    if quoted {
+        let doubled: String = [quote, quote].iter().collect();
+        format!("{}{}{}", quote, field.replace(quote, &doubled), quote)
+    } else {
+        field.to_string()
+    }
+}
+
+/// Writes delimited records, encoding each field with the configured quote style
+pub struct CsvWriter<W: Write> {
+    writer: W,
+    delimiter: char,
+    quote: char,
+    quote_style: QuoteStyle,
+}
+
+impl<W: Write> CsvWriter<W> {
+    pub fn new(writer: W, delimiter: char, quote_style: QuoteStyle) -> Self {
+        CsvWriter { writer, delimiter, quote: '"', quote_style }
+    }
+
+    /// Quotes fields with `quote` instead of `"`
+    pub fn quote(mut self, quote: char) -> Self {
+        self.quote = quote;
+        self
+    }
+
+    /// Writes one row followed by a newline
+    pub fn write_record<'a>(&mut self, fields: impl IntoIterator<Item = &'a String>) -> Result<()> {
+        for (i, field) in fields.into_iter().enumerate() {
+            if i > 0 {
+                write!(self.writer, "{}", self.delimiter)?;
+            }
+            write!(self.writer, "{}", encode_field(field, self.delimiter, self.quote, self.quote_style))?;
+        }
+        writeln!(self.writer)
+    }
+
+    pub fn flush(&mut self) -> Result<()> {
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_quote_styles() -> Result<()> {
+        let fields: Vec<String> = vec!["42".into(), "-1.5e3".into(), "Smith, Jr.".into(), "say \"hi\"".into(), "".into()];
+        let expected = [
+            (QuoteStyle::Necessary, "42,-1.5e3,\"Smith, Jr.\",\"say \"\"hi\"\"\",\n"),
+            (QuoteStyle::Always, "\"42\",\"-1.5e3\",\"Smith, Jr.\",\"say \"\"hi\"\"\",\"\"\n"),
+            (QuoteStyle::NonNumeric, "42,-1.5e3,\"Smith, Jr.\",\"say \"\"hi\"\"\",\"\"\n"),
+            (QuoteStyle::Never, "42,-1.5e3,Smith, Jr.,say \"hi\",\n"),
+        ];
+        for (quote_style, line) in expected {
+            let mut buffer: Vec<u8> = Vec::new();
+            CsvWriter::new(&mut buffer, ',', quote_style).write_record(&fields)?;
+            assert_eq!(String::from_utf8(buffer).unwrap(), line, "Unexpected output for {:?}", quote_style);
+        }
+
+        let mut buffer: Vec<u8> = Vec::new();
+        CsvWriter::new(&mut buffer, ';', QuoteStyle::Necessary).quote('\'').write_record(&fields)?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "42;-1.5e3;Smith, Jr.;say \"hi\";\n", "Only the configured quote should need quoting");
+        let mut buffer: Vec<u8> = Vec::new();
+        CsvWriter::new(&mut buffer, '|', QuoteStyle::Necessary).quote('\'').write_record(&["it's".to_string(), "a|b".to_string()])?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "'it''s'|'a|b'\n");
+        Ok(())
+    }
+
+    #[test]
+    fn test_is_numeric() {
+        for number in ["0", "42", "-7", "+3.25", ".5", "1e10", "6.02E-23"] {
+            assert!(is_numeric(number), "{} should be numeric", number);
+        }
+        for text in ["", "-", ".", "1.2.3", "1e", "inf", "NaN", "0x1F", "12 "] {
+            assert!(!is_numeric(text), "{:?} should not be numeric", text);
+        }
+    }
+}
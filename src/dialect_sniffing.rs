@@ -0,0 +1,102 @@
+use crate::csv_combining::CsvDialect;
+use crate::csv_reading::{parse_line, Trim};
+
+/// Delimiters tried when sniffing, in order of preference on a tie
+const DELIMITERS: [char; 4] = [',', '\t', ';', '|'];
+/// Quote characters tried when sniffing, in order of preference on a tie
+const QUOTES: [char; 2] = ['"', '\''];
+/// Records looked at when sniffing
+const SAMPLE_RECORDS: usize = 50;
+
+fn sample_records(lines: &[&str], delimiter: char, quote: char) -> Vec<Vec<String>> {
+    //multiline quoted fields are joined back together so they count as one record
+    let mut records: Vec<Vec<String>> = Vec::new();
+    let mut in_quotes = false;
+    for line in lines {
+        let (mut fields, open_quote) = parse_line(line, delimiter, quote, Trim::None, in_quotes);
+        if in_quotes && let Some(record) = records.last_mut() {
+            let continued = fields.remove(0);
+            record.last_mut().unwrap().push_str(&continued);
+            record.extend(fields);
+        } else {
+            records.push(fields);
+        }
+        in_quotes = open_quote.is_some();
+        if records.len() == SAMPLE_RECORDS && !in_quotes {
+            break;
+        }
+    }
+    records
+}
+
+/// Share of records with the most common field count, and that field count
+fn consistency(records: &[Vec<String>]) -> (f64, usize) {
+    let mut counts: Vec<(usize, usize)> = Vec::new(); //field count and how many records have it
+    for record in records {
+        match counts.iter_mut().find(|(field_count, _)| *field_count == record.len()) {
+            Some((_, records_with_count)) => *records_with_count += 1,
+            None => counts.push((record.len(), 1)),
+        }
+    }
+    let Some(&(field_count, records_with_count)) = counts.iter().max_by_key(|&&(field_count, records_with_count)| (records_with_count, field_count)) else {
+        return (0.0, 0);
+    };
+    (records_with_count as f64 / records.len() as f64, field_count)
+}
+
+fn is_number(value: &str) -> bool {
+    value.trim().parse::<f64>().is_ok_and(|number| number.is_finite())
+}
+
+/// Works out the delimiter, quote character and header row of an input from its first lines.
+///
+/// Every combination of `,` tab `;` `|` and `"` `'` is tried, and the one that splits the most records into the
+/// same number of fields (at least 2) wins, earlier candidates first on a tie.  The first row is taken as data
+/// instead of a header if any of its fields is a number.  Anything not found keeps the value from `fallback`,
+/// and a trailing incomplete line in `sample` is ignored
+pub fn sniff_dialect(sample: &str, fallback: &CsvDialect) -> CsvDialect {
+    let mut lines: Vec<&str> = sample.lines().collect();
+    if lines.len() > 1 && !sample.ends_with('\n') {
+        lines.pop(); //cut off where the sample ends
+    }
+    let mut best: Option<(f64, usize, char, char)> = None; //consistency, field count, delimiter, quote
+    for quote in QUOTES {
+        for delimiter in DELIMITERS {
+            let (share, field_count) = consistency(&sample_records(&lines, delimiter, quote));
+            if field_count > 1 && best.is_none_or(|(best_share, best_count, _, _)| (share, field_count) > (best_share, best_count)) {
+                best = Some((share, field_count, delimiter, quote));
+            }
+        }
+    }
+    let (delimiter, quote) = best.map_or((fallback.delimiter, fallback.quote), |(_, _, delimiter, quote)| (delimiter, quote));
+    let records = sample_records(&lines, delimiter, quote);
+    let has_header = match records.first() {
+        Some(first) if records.len() > 1 => !first.iter().any(|field| is_number(field)),
+        _ => fallback.has_header,
+    };
+    CsvDialect { delimiter, quote, has_header, sniff: false, ..fallback.clone() }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_sniff_dialect() {
+        let fallback = CsvDialect::default();
+        let tsv = sniff_dialect("id\tname\tnotes\n1\tAna\t\"a, b; c\"\n2\tWei\tx\n", &fallback);
+        assert_eq!((tsv.delimiter, tsv.quote, tsv.has_header), ('\t', '"', true));
+
+        let semicolons = sniff_dialect("id;name;city\n1;'Lopez; Ana';Madrid\n2;'Chen, Wei';Lyon\n3;Kim;Ro", &fallback);
+        assert_eq!((semicolons.delimiter, semicolons.quote), (';', '\''), "Single quotes should be found from the quoted delimiter");
+
+        let multiline = sniff_dialect("id|notes\n1|\"two\nlines\"\n2|plain\n", &fallback);
+        assert_eq!(multiline.delimiter, '|', "Multiline fields should count as one record");
+
+        let headerless = sniff_dialect("1,Alice Johnson,Engineering\n2,Bob Smith,Marketing\n", &fallback);
+        assert!(!headerless.has_header, "A first row with numbers should be data");
+
+        let one_column = sniff_dialect("name\nAlice\nBob\n", &CsvDialect { delimiter: ';', ..CsvDialect::default() });
+        assert_eq!((one_column.delimiter, one_column.has_header), (';', true), "Without a clear delimiter the fallback should be kept");
+    }
+}
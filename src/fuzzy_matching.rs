@@ -0,0 +1,197 @@
+use std::fmt;
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use crate::conflict_report::{json_string, ReportFormat};
+use crate::csv_writing::{CsvWriter, QuoteStyle};
+
+/// How close two keys must be to count as the same key when exact matching fails
+///
+/// Every key column is compared on its own (after any normalization) and all of them have to be close enough.
+/// Empty values only match empty values.  Each new key is compared with every key kept so far, so this is
+/// quadratic in the number of distinct keys.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FuzzyKeys {
+    /// At most this many single-character insertions, deletions or substitutions per key column
+    EditDistance(usize),
+    /// Jaro-Winkler similarity of at least this much (0.0 to 1.0) per key column
+    JaroWinkler(f64),
+}
+
+impl FuzzyKeys {
+    /// How far apart two keys are, `None` if they are too far apart.  Lower is closer
+    fn distance(&self, a: &[String], b: &[String]) -> Option<f64> {
+        let mut total = 0.0;
+        for (a, b) in a.iter().zip(b) {
+            if a == b {
+                continue;
+            }
+            if a.is_empty() || b.is_empty() {
+                return None;
+            }
+            total += match *self {
+                FuzzyKeys::EditDistance(max_distance) => {
+                    let distance = strsim::levenshtein(a, b);
+                    if distance > max_distance {
+                        return None;
+                    }
+                    distance as f64
+                }
+                FuzzyKeys::JaroWinkler(min_similarity) => {
+                    let similarity = strsim::jaro_winkler(a, b);
+                    if similarity < min_similarity {
+                        return None;
+                    }
+                    1.0 - similarity
+                }
+            };
+        }
+        Some(total)
+    }
+
+    /// Index of the closest key, the earliest one on a tie
+    pub(crate) fn closest<'a>(&self, key: &[String], candidates: impl IntoIterator<Item = &'a Vec<String>>) -> Option<(usize, f64)> {
+        let mut closest: Option<(usize, f64)> = None;
+        for (index, candidate) in candidates.into_iter().enumerate() {
+            if let Some(distance) = self.distance(key, candidate)
+                && closest.is_none_or(|(_, closest_distance)| distance < closest_distance)
+            {
+                closest = Some((index, distance));
+            }
+        }
+        closest
+    }
+
+    /// The score reported for a match: total edit distance, or the lowest similarity of any key column
+    pub(crate) fn score(&self, a: &[String], b: &[String]) -> f64 {
+        match *self {
+            FuzzyKeys::EditDistance(_) => a.iter().zip(b).map(|(a, b)| strsim::levenshtein(a, b) as f64).sum(),
+            FuzzyKeys::JaroWinkler(_) => a.iter().zip(b).map(|(a, b)| strsim::jaro_winkler(a, b)).fold(1.0, f64::min),
+        }
+    }
+}
+
+impl fmt::Display for FuzzyKeys {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            FuzzyKeys::EditDistance(max_distance) => write!(f, "levenshtein:{}", max_distance),
+            FuzzyKeys::JaroWinkler(min_similarity) => write!(f, "jaro-winkler:{}", min_similarity),
+        }
+    }
+}
+
+/// Parses `levenshtein:MAX_DISTANCE` and `jaro-winkler:MIN_SIMILARITY`
+impl FromStr for FuzzyKeys {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s.split_once(':') {
+            Some(("levenshtein", max_distance)) => max_distance.parse::<usize>()
+                .map(FuzzyKeys::EditDistance)
+                .map_err(|e| format!("invalid edit distance '{}': {}", max_distance, e)),
+            Some(("jaro-winkler", min_similarity)) => match min_similarity.parse::<f64>() {
+                Ok(similarity) if (0.0..=1.0).contains(&similarity) => Ok(FuzzyKeys::JaroWinkler(similarity)),
+                _ => Err(format!("invalid similarity '{}', expected a number from 0 to 1", min_similarity)),
+            },
+            _ => Err(format!("invalid fuzzy matching '{}', expected levenshtein:MAX_DISTANCE or jaro-winkler:MIN_SIMILARITY", s)),
+        }
+    }
+}
+
+/// A row whose key was matched to an earlier key without being equal to it
+#[derive(Debug, Clone, PartialEq)]
+pub struct FuzzyMatch {
+    /// Key column names and the values of the row it was matched to, as read
+    pub key: Vec<(String, String)>,
+    /// This row's key values, as read
+    pub matched: Vec<String>,
+    pub path: String,
+    /// 1-based line the row starts on
+    pub line: usize,
+    /// Total edit distance, or the lowest Jaro-Winkler similarity of any key column
+    pub score: f64,
+}
+
+fn write_csv<W: Write>(key_columns: &[String], matches: &[FuzzyMatch], writer: W) -> io::Result<()> {
+    let mut csv_writer = CsvWriter::new(writer, ',', QuoteStyle::Necessary);
+    let mut header = key_columns.to_vec();
+    header.extend(key_columns.iter().map(|column| format!("matched_{}", column)));
+    header.extend(["file", "line", "score"].map(String::from));
+    csv_writer.write_record(&header)?;
+    for fuzzy_match in matches {
+        let mut row: Vec<String> = fuzzy_match.key.iter().map(|(_, value)| value.clone()).collect();
+        row.extend(fuzzy_match.matched.iter().cloned());
+        row.extend([fuzzy_match.path.clone(), fuzzy_match.line.to_string(), fuzzy_match.score.to_string()]);
+        csv_writer.write_record(&row)?;
+    }
+    csv_writer.flush()
+}
+
+fn write_json<W: Write>(matches: &[FuzzyMatch], mut writer: W) -> io::Result<()> {
+    writeln!(writer, "[")?;
+    for (i, fuzzy_match) in matches.iter().enumerate() {
+        let key: Vec<String> = fuzzy_match.key.iter().map(|(column, value)| format!("{}: {}", json_string(column), json_string(value))).collect();
+        let matched: Vec<String> = fuzzy_match.key.iter().zip(&fuzzy_match.matched).map(|((column, _), value)| format!("{}: {}", json_string(column), json_string(value))).collect();
+        let comma = if i + 1 < matches.len() { "," } else { "" };
+        writeln!(writer, "  {{\"key\": {{{}}}, \"matched\": {{{}}}, \"file\": {}, \"line\": {}, \"score\": {}}}{}",
+            key.join(", "), matched.join(", "), json_string(&fuzzy_match.path), fuzzy_match.line, fuzzy_match.score, comma)?;
+    }
+    writeln!(writer, "]")?;
+    writer.flush()
+}
+
+/// Writes the rows matched fuzzily for review.  A CSV report starts with `key_columns`, even without matches
+pub fn write_fuzzy_report<W: Write>(key_columns: &[String], matches: &[FuzzyMatch], writer: W, format: ReportFormat) -> io::Result<()> {
+    match format {
+        ReportFormat::Csv => write_csv(key_columns, matches, writer),
+        ReportFormat::Json => write_json(matches, writer),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn key(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn test_closest_key() {
+        let kept = [key(&["John Smith", "Boston"]), key(&["Jon Smyth", "Boston"]), key(&["Jon Smith", ""])];
+        let edit_distance = FuzzyKeys::EditDistance(1);
+        assert_eq!(edit_distance.closest(&key(&["Jon Smith", "Boston"]), &kept), Some((0, 1.0)), "Every key column should be close enough");
+        assert_eq!(edit_distance.closest(&key(&["Jon Smith", "Austin"]), &kept), None);
+        assert_eq!(edit_distance.closest(&key(&["Jon Smith", "x"]), &kept), None, "Empty values should only match empty values");
+
+        let jaro_winkler: FuzzyKeys = "jaro-winkler:0.9".parse().unwrap();
+        assert_eq!(jaro_winkler.closest(&key(&["Jon Smyth", "Boston"]), &kept).map(|(i, _)| i), Some(1), "An equal key should be closest");
+        assert!(jaro_winkler.score(&kept[0], &kept[1]) < 1.0);
+
+        assert_eq!("levenshtein:2".parse::<FuzzyKeys>(), Ok(FuzzyKeys::EditDistance(2)));
+        assert!("jaro-winkler:1.5".parse::<FuzzyKeys>().is_err());
+        assert!("soundex".parse::<FuzzyKeys>().is_err());
+    }
+
+    #[test]
+    fn test_fuzzy_report() -> io::Result<()> {
+        let fuzzy_match = FuzzyMatch {
+            key: vec![("name".to_string(), "John Smith".to_string())],
+            matched: key(&["Jon Smith"]),
+            path: "crm2.csv".to_string(),
+            line: 4,
+            score: 1.0,
+        };
+        let key_columns = key(&["name"]);
+        let mut buffer: Vec<u8> = Vec::new();
+        write_fuzzy_report(&key_columns, std::slice::from_ref(&fuzzy_match), &mut buffer, ReportFormat::Csv)?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "name,matched_name,file,line,score\nJohn Smith,Jon Smith,crm2.csv,4,1\n");
+        let mut buffer: Vec<u8> = Vec::new();
+        write_fuzzy_report(&key_columns, &[], &mut buffer, ReportFormat::Csv)?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "name,matched_name,file,line,score\n", "A report without matches should still have the key columns");
+        let mut buffer: Vec<u8> = Vec::new();
+        write_fuzzy_report(&key_columns, &[fuzzy_match], &mut buffer, ReportFormat::Json)?;
+        assert_eq!(String::from_utf8(buffer).unwrap(), "[\n  {\"key\": {\"name\": \"John Smith\"}, \"matched\": {\"name\": \"Jon Smith\"}, \"file\": \"crm2.csv\", \"line\": 4, \"score\": 1}\n]\n");
+        Ok(())
+    }
+}
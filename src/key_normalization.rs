@@ -0,0 +1,155 @@
+use std::borrow::Cow;
+use std::fmt;
+use std::str::FromStr;
+
+use unicode_normalization::UnicodeNormalization;
+
+/// How a key column's values are rewritten before rows are compared.  The output keeps the values as read
+///
+/// Steps run in the order the fields are listed.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct KeyNormalization {
+    /// Remove leading and trailing whitespace
+    pub trim: bool,
+    /// Unicode NFC, so composed and decomposed accents match
+    pub nfc: bool,
+    /// Compare case-insensitively: Unicode lowercase, then ß as ss, final ς as σ and long ſ as s
+    pub case_fold: bool,
+    /// Remove leading zeros, keeping at least one character: 007 and 7 match, 00A1 and A1 match
+    pub strip_zeros: bool,
+    /// Write numbers in one form: +42, 042, 42.0 and 4.2e1 all match 42.  Anything else is left alone
+    pub numeric: bool,
+}
+
+impl KeyNormalization {
+    pub fn is_none(&self) -> bool {
+        *self == KeyNormalization::default()
+    }
+
+    /// The value rows are matched on
+    pub fn apply<'a>(&self, value: &'a str) -> Cow<'a, str> {
+        let mut value = Cow::Borrowed(value);
+        if self.trim {
+            value = match value {
+                Cow::Borrowed(v) => Cow::Borrowed(v.trim()),
+                Cow::Owned(v) => Cow::Owned(v.trim().to_string()),
+            };
+        }
+        if self.nfc && !unicode_normalization::is_nfc(&value) {
+            value = Cow::Owned(value.nfc().collect());
+        }
+        if self.case_fold && value.chars().any(|c| c.is_uppercase() || matches!(c, 'ß' | 'ς' | 'ſ')) {
+            value = Cow::Owned(case_fold(&value));
+        }
+        if self.strip_zeros {
+            let stripped = value.trim_start_matches('0');
+            if stripped.len() != value.len() {
+                value = Cow::Owned(if stripped.is_empty() { "0".to_string() } else { stripped.to_string() });
+            }
+        }
+        if self.numeric && let Some(number) = canonical_number(&value) {
+            value = Cow::Owned(number);
+        }
+        value
+    }
+}
+
+fn case_fold(value: &str) -> String {
+    //the full case folds that lowercasing misses in the scripts keys are usually written in
+    let mut folded = String::with_capacity(value.len());
+    for c in value.to_lowercase().chars() {
+        match c {
+            'ß' => folded.push_str("ss"),
+            'ς' => folded.push('σ'),
+            'ſ' => folded.push('s'),
+            c => folded.push(c),
+        }
+    }
+    folded
+}
+
+fn canonical_number(value: &str) -> Option<String> {
+    //plain decimals are rewritten as text so long ids keep every digit, only exponent forms go through f64
+    let (negative, unsigned) = match value.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, value.strip_prefix('+').unwrap_or(value)),
+    };
+    let (integer, fraction) = unsigned.split_once('.').unwrap_or((unsigned, ""));
+    let is_digits = |s: &str| s.chars().all(|c| c.is_ascii_digit());
+    if !(integer.is_empty() && fraction.is_empty()) && is_digits(integer) && is_digits(fraction) {
+        let integer = integer.trim_start_matches('0');
+        let fraction = fraction.trim_end_matches('0');
+        let mut number = if integer.is_empty() { "0".to_string() } else { integer.to_string() };
+        if !fraction.is_empty() {
+            number.push('.');
+            number.push_str(fraction);
+        }
+        if negative && number != "0" {
+            number.insert(0, '-');
+        }
+        return Some(number);
+    }
+    if !value.contains(['e', 'E']) {
+        return None; //inf, nan and the like are not numbers here
+    }
+    let number = value.parse::<f64>().ok().filter(|number| number.is_finite())?;
+    canonical_number(&(if number == 0.0 { 0.0 } else { number }).to_string())
+}
+
+impl fmt::Display for KeyNormalization {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let steps = [(self.trim, "trim"), (self.nfc, "nfc"), (self.case_fold, "casefold"), (self.strip_zeros, "strip-zeros"), (self.numeric, "numeric")];
+        let names: Vec<&str> = steps.iter().filter(|(enabled, _)| *enabled).map(|&(_, name)| name).collect();
+        if names.is_empty() { write!(f, "none") } else { write!(f, "{}", names.join(",")) }
+    }
+}
+
+/// Parses a comma-separated list of `trim`, `nfc`, `casefold`, `strip-zeros` and `numeric`, or `none`
+impl FromStr for KeyNormalization {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut normalization = KeyNormalization::default();
+        for step in s.split(',') {
+            match step.trim() {
+                "none" => {}
+                "trim" => normalization.trim = true,
+                "nfc" => normalization.nfc = true,
+                "casefold" => normalization.case_fold = true,
+                "strip-zeros" => normalization.strip_zeros = true,
+                "numeric" => normalization.numeric = true,
+                other => return Err(format!("unknown key normalization '{}', expected trim, nfc, casefold, strip-zeros, numeric or none", other)),
+            }
+        }
+        Ok(normalization)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_normalization_steps() {
+        let all: KeyNormalization = "trim,nfc,casefold,strip-zeros,numeric".parse().unwrap();
+        assert_eq!(all.to_string(), "trim,nfc,casefold,strip-zeros,numeric");
+        assert_eq!(all.apply("  Jos\u{65}\u{301} "), "jos\u{e9}", "Decomposed accents should match composed ones");
+        assert_eq!(all.apply("00A1"), "a1");
+        assert_eq!(all.apply("000"), "0");
+
+        let case_fold = KeyNormalization { case_fold: true, ..KeyNormalization::default() };
+        assert_eq!(case_fold.apply("STRASSE"), case_fold.apply("stra\u{df}e"), "\u{df} should fold to ss");
+        assert_eq!(case_fold.apply("Stra\u{1e9e}e"), "strasse", "Capital \u{1e9e} should fold to ss");
+        assert_eq!(case_fold.apply("\u{39f}\u{394}\u{3a5}\u{3a3}\u{3a3}\u{395}\u{3a5}\u{3a3}"), case_fold.apply("\u{3bf}\u{3b4}\u{3c5}\u{3c3}\u{3c3}\u{3b5}\u{3c5}\u{3c2}"), "Final sigma should match \u{3c3}");
+
+        let numeric = KeyNormalization { numeric: true, ..KeyNormalization::default() };
+        for (value, expected) in [("+042", "42"), ("42.50", "42.5"), ("-0.0", "0"), (".5", "0.5"), ("4.2e1", "42"), ("12345678901234567890", "12345678901234567890")] {
+            assert_eq!(numeric.apply(value), expected, "Unexpected canonical form of {:?}", value);
+        }
+        for value in ["42a", "inf", "-", ".", " 42"] {
+            assert_eq!(numeric.apply(value), value, "{:?} is not a number and should be left alone", value);
+        }
+        assert!(matches!(KeyNormalization::default().apply("42"), Cow::Borrowed(_)), "No normalization should not allocate");
+        assert!("lowercase".parse::<KeyNormalization>().is_err());
+    }
+}
@@ -0,0 +1,30 @@
+//! Combine CSV files with different schemas into one file.
+//!
+//! Columns are matched by header name, the output header is the union of all input headers and
+//! rows can be deduplicated or merged on key columns.  [`Combiner`] is the entry point.
+
+pub mod combine_error;
+pub mod conflict_report;
+pub mod combiner;
+pub mod csv_combining;
+pub mod csv_diffing;
+pub mod csv_reading;
+pub mod csv_writing;
+pub mod dialect_sniffing;
+pub mod fuzzy_matching;
+pub mod key_normalization;
+pub mod merge_strategy;
+pub mod text_encoding;
+
+pub use combine_error::{CombineError, Result};
+pub use combiner::Combiner;
+pub use conflict_report::{write_conflict_report, ConflictCandidate, ConflictHandling, MergeConflict, ReportFormat};
+pub use csv_combining::{combine_files_by_keys, combine_files_into, CombinedRecords, CombineSummary, CsvDialect, Delimiter, DuplicateHandling, FileDialect, Input, JoinMode, KeepDuplicate, KeyPresence, RaggedRows, Record, SetOperation};
+pub use csv_diffing::{diff_records, write_diff, ChangeType, ColumnChange, RowChange, SnapshotDiff};
+pub use csv_reading::{CsvReader, ParseError, Trim};
+pub use csv_writing::{CsvWriter, QuoteStyle};
+pub use dialect_sniffing::sniff_dialect;
+pub use fuzzy_matching::{write_fuzzy_report, FuzzyKeys, FuzzyMatch};
+pub use key_normalization::KeyNormalization;
+pub use merge_strategy::MergeStrategy;
+pub use text_encoding::{detect_encoding, EncodingWriter, TextEncoding};
@@ -1,45 +1,267 @@
-mod csv_combining;
-
 use clap::Parser;
+use std::io::{self, BufReader, BufWriter, ErrorKind};
+use std::fs::File;
 use std::process;
+use csv_combiner::{write_conflict_report, write_fuzzy_report, ChangeType, CombineError, CombineSummary, Combiner, ConflictHandling, Delimiter, DuplicateHandling, EncodingWriter, FileDialect, FuzzyKeys, JoinMode, KeepDuplicate, KeyNormalization, KeyPresence, MergeStrategy, QuoteStyle, RaggedRows, ReportFormat, SetOperation, TextEncoding, Trim};
+use csv_combiner::text_encoding::parse_output_encoding;
+use encoding_rs::{Encoding, UTF_8};
 
 /// Combine multiple CSV files with different schemas into a single output file
 #[derive(Parser)]
 #[command(version, about, long_about = None)]
 struct Args {
-    /// Input CSV files to combine (at least one required)
+    /// Input CSV files to combine (at least one required, "-" reads stdin)
     #[arg(required = true)]
     input_files: Vec<String>,
     
-    /// Output file path
+    /// Output file path ("-" or omitted for stdout)
     #[arg(short = 'o', long)]
-    output: String,
+    output: Option<String>,
     
-    /// Field delimiter character
+    /// Field delimiter: a character, a name (tab, comma, semicolon, pipe, space), \t or \xNN, or auto to detect each input's delimiter, quote character and header row
     #[arg(short = 'd', long, default_value = ",")]
-    delimiter: char,
+    delimiter: Delimiter,
+    
+    /// Delimiter of the inputs, or auto, overriding --delimiter for them
+    #[arg(long, value_name = "DELIMITER")]
+    input_delimiter: Option<Delimiter>,
+    
+    /// Delimiter of the output, in any --delimiter form but auto, overriding --delimiter for it [default: the --delimiter character, or ',' with auto]
+    #[arg(long, value_name = "CHAR", value_parser = Delimiter::parse_char)]
+    output_delimiter: Option<char>,
+    
+    /// Quote character of the inputs
+    #[arg(long, value_name = "CHAR", default_value_t = '"')]
+    input_quote: char,
+    
+    /// Quote character of the output
+    #[arg(long, value_name = "CHAR", default_value_t = '"')]
+    output_quote: char,
+    
+    /// Delimiter of one input, e.g. data.tsv=auto, overriding --delimiter for it, repeatable
+    #[arg(long, value_name = "FILE=DELIMITER", value_parser = parse_file_delimiter)]
+    file_delimiter: Vec<(String, Delimiter)>,
+    
+    /// Delimiter, quote character and header row of one input, e.g. data.tsv=tab,',noheader, overriding
+    /// --file-delimiter, --input-quote and a detected header for it, repeatable
+    #[arg(long, value_name = "FILE=DELIMITER[,QUOTE][,header|noheader]", value_parser = parse_file_dialect)]
+    file_dialect: Vec<(String, FileDialect)>,
+    
+    /// Character encoding of the inputs: auto or a label like utf-8, utf-16le, windows-1252 or latin1.
+    /// ENCODING for every input or FILE=ENCODING, repeatable [default: auto]
+    #[arg(long, value_name = "[FILE=]ENCODING", value_parser = parse_encoding)]
+    encoding: Vec<(Option<String>, TextEncoding)>,
+    
+    /// Character encoding of the output, diff and reports, e.g. windows-1252 for programs that expect Latin-1 [default: utf-8]
+    #[arg(long, value_name = "ENCODING", value_parser = parse_output_encoding)]
+    output_encoding: Option<&'static Encoding>,
+    
+    /// When to quote output fields
+    #[arg(long, value_enum, default_value_t = QuoteStyle::Necessary)]
+    quote_style: QuoteStyle,
+    
+    /// Which input fields to trim leading and trailing whitespace from
+    #[arg(long, value_enum, default_value_t = Trim::None)]
+    trim: Trim,
+    
+    /// Close unterminated quoted fields at end of file with a warning instead of failing
+    #[arg(long)]
+    lenient: bool,
     
     /// Key columns for deduplication (comma-separated)
     #[arg(short = 'k', long, value_delimiter = ',')]
     keys: Option<Vec<String>>,
     
+    /// Which inputs must have every --keys column [default: every]
+    #[arg(long, value_enum, value_name = "WHICH")]
+    key_presence: Option<KeyPresence>,
+    
+    /// Normalize key values before comparing them: STEPS for every key or COLUMN=STEPS, repeatable.
+    /// Comma-separated steps: trim, nfc, casefold, strip-zeros, numeric
+    #[arg(long, value_name = "[COLUMN=]STEPS", value_parser = parse_key_normalization)]
+    normalize_key: Vec<(Option<String>, KeyNormalization)>,
+    
+    /// Treat close keys as duplicates: levenshtein:MAX_DISTANCE or jaro-winkler:MIN_SIMILARITY per key column
+    #[arg(long, value_name = "METHOD:THRESHOLD")]
+    fuzzy_keys: Option<FuzzyKeys>,
+    
+    /// Write rows matched by --fuzzy-keys to this file, next to the key they were matched to
+    #[arg(long, value_name = "FILE")]
+    fuzzy_report: Option<String>,
+    
+    /// Format of the fuzzy match report [default: json for a .json file, otherwise csv]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    fuzzy_report_format: Option<ReportFormat>,
+    
     /// Remove duplicate rows based on key columns (keeps first)
     #[arg(short = 'r', long)]
     remove_duplicates: bool,
     
+    /// Which duplicate --remove-duplicates keeps: first, last, max:COLUMN or min:COLUMN [default: first]
+    #[arg(long, value_name = "RULE")]
+    keep: Option<KeepDuplicate>,
+    
+    /// Which distinct rows --remove-duplicates writes, by the inputs their key appears in: union (any), intersect (every),
+    /// except (the first and no other) or symmetric-difference (exactly one).  Whole rows are compared without --keys [default: union]
+    #[arg(long, value_enum, value_name = "OPERATION")]
+    set_op: Option<SetOperation>,
+    
     /// Merge duplicate rows based on key columns
     #[arg(short = 'm', long)]
     merge_duplicates: bool,
     
+    /// Which merged rows to write, by the inputs their key appears in: inner (every input), left (the first),
+    /// right (the last), full (any) or anti (the first and no other) [default: full]
+    #[arg(long, value_enum, value_name = "MODE")]
+    join: Option<JoinMode>,
+    
+    /// How merged duplicates combine values: STRATEGY for every column or COLUMN=STRATEGY, repeatable.
+    /// first-wins, last-wins, prefer-longest, prefer-non-empty-latest, concat[:SEPARATOR], max, min or sum
+    #[arg(long, value_name = "[COLUMN=]STRATEGY", value_parser = parse_merge_strategy)]
+    merge_strategy: Vec<(Option<String>, MergeStrategy)>,
+    
+    /// Write merged columns whose rows disagree to this file, with each value's source file and line
+    #[arg(long, value_name = "FILE")]
+    conflict_report: Option<String>,
+    
+    /// Format of the conflict report [default: json for a .json file, otherwise csv]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    conflict_report_format: Option<ReportFormat>,
+    
+    /// Fail before writing any merged row if rows disagree on a non-empty value
+    #[arg(long)]
+    fail_on_conflict: bool,
+    
+    /// Rank an input when removing or merging duplicates, e.g. hr.csv=10 (higher wins, default 0), repeatable
+    #[arg(long, value_name = "FILE=PRIORITY", value_parser = parse_priority, allow_hyphen_values = true)]
+    priority: Vec<(String, i32)>,
+    
+    /// Sort the output by these columns (comma-separated), numbers by value [default: input order]
+    #[arg(long, value_delimiter = ',')]
+    sort_by: Option<Vec<String>>,
+    
+    /// How to handle rows with a different number of fields than their header
+    #[arg(long, value_enum, default_value_t = RaggedRows::Pad)]
+    ragged_rows: RaggedRows,
+    
     /// Value to use for missing columns [default: ""]
     #[arg(short = 'e', long, default_value = "", hide_default_value = true)]
     empty_value: String,
     
+    /// Compare exactly two inputs, the old snapshot then the new one, and write the added, removed and changed rows
+    /// by --keys instead of combining them
+    #[arg(long)]
+    diff: bool,
+    
+    /// Format of the --diff output [default: json for a .json output file, otherwise csv]
+    #[arg(long, value_enum, value_name = "FORMAT")]
+    diff_format: Option<ReportFormat>,
+    
     /// Display license information
     #[arg(long)]
     license: bool,
 }
 
+/// Name "-" inputs go by in messages
+const STDIN_NAME: &str = "<stdin>";
+
+/// Splits "salary=max" into column and strategy.  Without a column the strategy applies to every column.
+/// Column names may contain ':' and '=', and a concat separator may contain both too, so a column is split
+/// off before "=concat:" and otherwise at the last '='
+fn parse_merge_strategy(value: &str) -> Result<(Option<String>, MergeStrategy), String> {
+    let split = match value.find("=concat:") {
+        _ if value.starts_with("concat:") => None,
+        Some(i) => Some((&value[..i], &value[i + 1..])),
+        None => value.rsplit_once('='),
+    };
+    match split {
+        Some((column, strategy)) => Ok((Some(column.to_string()), strategy.parse()?)),
+        None => Ok((None, value.parse()?)),
+    }
+}
+
+/// Splits "email=trim,casefold" into column and steps.  Without a column the steps apply to every key
+fn parse_key_normalization(value: &str) -> Result<(Option<String>, KeyNormalization), String> {
+    match value.split_once('=') {
+        Some((column, steps)) => Ok((Some(column.to_string()), steps.parse()?)),
+        None => Ok((None, value.parse()?)),
+    }
+}
+
+/// Splits "hr.csv=10" at the last '=', so file names may contain one
+fn parse_priority(value: &str) -> Result<(String, i32), String> {
+    let Some((input, priority)) = value.rsplit_once('=') else {
+        return Err("expected FILE=PRIORITY".to_string());
+    };
+    let priority = priority.parse::<i32>().map_err(|e| format!("invalid priority '{}': {}", priority, e))?;
+    Ok((input.to_string(), priority))
+}
+
+/// Splits "data.txt=|" at the last '=', so file names may contain one.  "data.txt==" is '=' as the delimiter
+fn parse_file_delimiter(value: &str) -> Result<(String, Delimiter), String> {
+    let split = match value.strip_suffix("==") {
+        Some(input) => Some((input, "=")),
+        None => value.rsplit_once('='),
+    };
+    let Some((input, delimiter)) = split else {
+        return Err("expected FILE=DELIMITER".to_string());
+    };
+    Ok((input.to_string(), delimiter.parse()?))
+}
+
+/// Splits "data.txt=tab,'" at the last '=' that leaves a valid dialect, so file names may contain one and "data.txt==,'"
+/// has '=' as the delimiter
+fn parse_file_dialect(value: &str) -> Result<(String, FileDialect), String> {
+    let mut error = "expected FILE=DELIMITER[,QUOTE][,header|noheader]".to_string();
+    for (index, _) in value.rmatch_indices('=') {
+        match value[index + 1..].parse() {
+            Ok(dialect) => return Ok((value[..index].to_string(), dialect)),
+            Err(e) if error.starts_with("expected") => error = e,
+            Err(_) => {}
+        }
+    }
+    Err(error)
+}
+
+/// Splits "legacy.csv=windows-1252" at the last '=', so file names may contain one.  Without a file the encoding is for every input
+fn parse_encoding(value: &str) -> Result<(Option<String>, TextEncoding), String> {
+    match value.rsplit_once('=') {
+        Some((input, encoding)) => Ok((Some(input.to_string()), encoding.parse()?)),
+        None => Ok((None, value.parse()?)),
+    }
+}
+
+/// Distinct exit codes so scripts can tell failures apart (1 is usage errors, 2 is clap's own)
+fn exit_code(error: &CombineError) -> i32 {
+    match error {
+        CombineError::Config { .. } => 1,
+        CombineError::InputNotFound { .. } => 3,
+        CombineError::Io { .. } => 4,
+        CombineError::Parse(_) => 5,
+        CombineError::Schema { .. } => 6,
+        CombineError::Key { .. } | CombineError::MissingKeys { .. } => 7,
+        CombineError::Conflict { .. } => 8,
+    }
+}
+
+fn write_report(path: &str, format: Option<ReportFormat>, encoding: &'static Encoding, write: impl FnOnce(BufWriter<EncodingWriter<File>>, ReportFormat) -> io::Result<()>) {
+    let format = format.unwrap_or_else(|| ReportFormat::from_path(path));
+    if let Err(e) = File::create(path).and_then(|file| write(BufWriter::new(EncodingWriter::new(file, encoding)), format)) {
+        let error = CombineError::io(path, e);
+        eprintln!("Error: {}", error);
+        process::exit(exit_code(&error));
+    }
+}
+
+fn print_warnings(summary: &CombineSummary) {
+    for warning in &summary.warnings {
+        eprintln!("Warning: {}", warning);
+    }
+    if summary.ragged_rows > 0 {
+        eprintln!("Warning: {} rows had a different number of fields than their header", summary.ragged_rows);
+    }
+}
+
 fn main() {
     let args = Args::parse();
     
@@ -59,29 +281,245 @@ fn main() {
         process::exit(1);
     }
     
-    // Convert input_files Vec<String> to Vec<&str>
-    let input_refs: Vec<&str> = args.input_files.iter().map(|s| s.as_str()).collect();
+    if args.diff && (args.remove_duplicates || args.merge_duplicates || args.sort_by.is_some()) {
+        eprintln!("Error: --diff cannot be used with --remove-duplicates, --merge-duplicates or --sort-by");
+        process::exit(1);
+    }
+    if args.diff && args.keys.is_none() {
+        eprintln!("Error: --diff requires --keys");
+        process::exit(1);
+    }
+    if args.diff_format.is_some() && !args.diff {
+        eprintln!("Error: --diff-format requires --diff");
+        process::exit(1);
+    }
     
-    // Convert keys Option<Vec<String>> to Option<Vec<&str>>
-    let keys_refs: Option<Vec<&str>> = args.keys.as_ref()
-        .map(|v| v.iter().map(|s| s.as_str()).collect());
+    if !args.merge_strategy.is_empty() && !args.merge_duplicates {
+        eprintln!("Error: --merge-strategy requires --merge-duplicates");
+        process::exit(1);
+    }
+    if args.join.is_some() && !args.merge_duplicates {
+        eprintln!("Error: --join requires --merge-duplicates");
+        process::exit(1);
+    }
+    if (args.conflict_report.is_some() || args.fail_on_conflict) && !args.merge_duplicates {
+        eprintln!("Error: --conflict-report and --fail-on-conflict require --merge-duplicates");
+        process::exit(1);
+    }
     
-    // Call the combining function
-    let result = csv_combining::combine_files_by_keys(
-        &input_refs,
-        &args.output,
-        keys_refs.as_deref(),
-        args.delimiter,
-        &args.empty_value,
-        args.remove_duplicates,
-        args.merge_duplicates,
-    );
+    if args.key_presence.is_some() && args.keys.is_none() {
+        eprintln!("Error: --key-presence requires --keys");
+        process::exit(1);
+    }
+    if !args.normalize_key.is_empty() && !args.remove_duplicates && !args.merge_duplicates && !args.diff {
+        eprintln!("Error: --normalize-key requires --remove-duplicates, --merge-duplicates or --diff");
+        process::exit(1);
+    }
+    if args.fuzzy_keys.is_some() && !args.remove_duplicates && !args.merge_duplicates {
+        eprintln!("Error: --fuzzy-keys requires --remove-duplicates or --merge-duplicates");
+        process::exit(1);
+    }
+    if args.fuzzy_report.is_some() && args.fuzzy_keys.is_none() {
+        eprintln!("Error: --fuzzy-report requires --fuzzy-keys");
+        process::exit(1);
+    }
+    if args.set_op.is_some() && !args.remove_duplicates {
+        eprintln!("Error: --set-op requires --remove-duplicates");
+        process::exit(1);
+    }
+    if args.keep.is_some() && !args.remove_duplicates {
+        eprintln!("Error: --keep requires --remove-duplicates");
+        process::exit(1);
+    }
+    if !args.priority.is_empty() && !args.remove_duplicates && !args.merge_duplicates {
+        eprintln!("Error: --priority requires --remove-duplicates or --merge-duplicates");
+        process::exit(1);
+    }
     
-    // Handle errors
-    if let Err(e) = result {
-        eprintln!("Error: {}", e);
+    let conflicts = if args.fail_on_conflict {
+        ConflictHandling::Fail
+    } else if args.conflict_report.is_some() {
+        ConflictHandling::Report
+    } else {
+        ConflictHandling::Ignore
+    };
+    
+    let duplicates = if args.remove_duplicates {
+        DuplicateHandling::Remove
+    } else if args.merge_duplicates {
+        DuplicateHandling::Merge
+    } else {
+        DuplicateHandling::Keep
+    };
+    
+    // stdin can only be read once
+    if args.input_files.iter().filter(|&input| input == "-").count() > 1 {
+        eprintln!("Error: \"-\" (stdin) can only be given once as an input");
         process::exit(1);
     }
     
-    println!("Successfully combined {} files into {}", args.input_files.len(), args.output);
+    // "-" and no -o both mean stdout
+    let output_file = args.output.as_deref().filter(|&output| output != "-");
+    
+    let mut combiner = Combiner::new();
+    for input in &args.input_files {
+        combiner = if input == "-" {
+            combiner.input_reader(STDIN_NAME, BufReader::new(io::stdin()))
+        } else {
+            combiner.input(input)
+        };
+    }
+    let combiner = match args.input_delimiter.unwrap_or(args.delimiter) {
+        Delimiter::Char(delimiter) => combiner.delimiter(delimiter),
+        Delimiter::Auto => combiner.sniff(true),
+    };
+    // a sniffed delimiter is per input, so the output falls back to ','
+    let output_delimiter = match args.delimiter {
+        Delimiter::Char(delimiter) => delimiter,
+        Delimiter::Auto => ',',
+    };
+    let combiner = combiner
+        .output_delimiter(args.output_delimiter.unwrap_or(output_delimiter))
+        .quote(args.input_quote)
+        .output_quote(args.output_quote);
+    let combiner = args.file_delimiter.into_iter().fold(combiner, |combiner, (input, delimiter)| {
+        let input = if input == "-" { STDIN_NAME.to_string() } else { input };
+        combiner.file_delimiter(input, delimiter)
+    });
+    let combiner = args.file_dialect.into_iter().fold(combiner, |combiner, (input, dialect)| {
+        let input = if input == "-" { STDIN_NAME.to_string() } else { input };
+        combiner.file_dialect(input, dialect)
+    });
+    let combiner = args.encoding.into_iter().fold(combiner, |combiner, (input, encoding)| match input {
+        Some(input) if input == "-" => combiner.file_encoding(STDIN_NAME, encoding),
+        Some(input) => combiner.file_encoding(input, encoding),
+        None => combiner.encoding(encoding),
+    });
+    let output_encoding = args.output_encoding.unwrap_or(UTF_8);
+    let combiner = combiner.output_encoding(output_encoding);
+    let combiner = combiner
+        .quote_style(args.quote_style)
+        .trim(args.trim)
+        .lenient(args.lenient)
+        .empty_value(&args.empty_value)
+        .duplicates(duplicates)
+        .ragged_rows(args.ragged_rows)
+        .join(args.join.unwrap_or_default())
+        .set_operation(args.set_op.unwrap_or_default())
+        .whole_row_keys(args.set_op.is_some() && args.keys.is_none())
+        .conflicts(conflicts)
+        .keep(args.keep.clone().unwrap_or_default())
+        .key_presence(args.key_presence.unwrap_or_default());
+    let combiner = match &args.keys {
+        Some(keys) => combiner.keys(keys),
+        None => combiner,
+    };
+    let combiner = args.normalize_key.into_iter().fold(combiner, |combiner, (column, normalization)| match column {
+        Some(column) => combiner.normalize_key(column, normalization),
+        None => combiner.normalize_keys(normalization),
+    });
+    let combiner = args.merge_strategy.into_iter().fold(combiner, |combiner, (column, strategy)| match column {
+        Some(column) => combiner.column_merge_strategy(column, strategy),
+        None => combiner.merge_strategy(strategy),
+    });
+    let combiner = args.priority.into_iter().fold(combiner, |combiner, (input, priority)| {
+        let input = if input == "-" { STDIN_NAME.to_string() } else { input };
+        combiner.priority(input, priority)
+    });
+    let combiner = match args.fuzzy_keys {
+        Some(fuzzy_keys) => combiner.fuzzy_keys(fuzzy_keys),
+        None => combiner,
+    };
+    let combiner = match &args.sort_by {
+        Some(sort_by) => combiner.sort_by(sort_by),
+        None => combiner,
+    };
+    
+    if args.diff {
+        let format = args.diff_format.unwrap_or_else(|| output_file.map_or(ReportFormat::Csv, ReportFormat::from_path));
+        let result = match output_file {
+            Some(output_file) => File::create(output_file)
+                .map_err(|e| CombineError::io(output_file, e))
+                .and_then(|file| combiner.diff_to(BufWriter::new(file), format)),
+            None => combiner.diff_to(io::stdout().lock(), format),
+        };
+        let diff = match result {
+            Ok(diff) => diff,
+            Err(CombineError::Io { source, .. }) if output_file.is_none() && source.kind() == ErrorKind::BrokenPipe => {
+                process::exit(0);
+            }
+            Err(e) => {
+                eprintln!("Error: {}", e);
+                process::exit(exit_code(&e));
+            }
+        };
+        print_warnings(&diff.summary);
+        if let Some(output_file) = output_file {
+            println!("{} rows added, {} removed and {} changed, written to {}",
+                diff.count(ChangeType::Added), diff.count(ChangeType::Removed), diff.count(ChangeType::Changed), output_file);
+        }
+        return;
+    }
+    
+    let result = match output_file {
+        Some(output_file) => combiner.output(output_file).run(),
+        None => combiner.run_to(io::stdout().lock()),
+    };
+    
+    // Handle errors
+    let summary = match result {
+        Ok(summary) => summary,
+        Err(CombineError::Io { source, .. }) if output_file.is_none() && source.kind() == ErrorKind::BrokenPipe => {
+            process::exit(0); //downstream command (e.g. head) stopped reading, not an error
+        }
+        Err(CombineError::Conflict { key_columns, conflicts }) if args.conflict_report.is_some() => {
+            //the report is what the data steward needs to resolve the failure, so it is still written
+            let report = args.conflict_report.as_deref().unwrap();
+            write_report(report, args.conflict_report_format, output_encoding, |writer, format| write_conflict_report(&key_columns, &conflicts, writer, format));
+            let e = CombineError::Conflict { key_columns, conflicts };
+            eprintln!("Error: {} (all conflicts are listed in {})", e, report);
+            process::exit(exit_code(&e));
+        }
+        Err(e) => {
+            eprintln!("Error: {}", e);
+            process::exit(exit_code(&e));
+        }
+    };
+    print_warnings(&summary);
+    if let Some(report) = &args.conflict_report {
+        write_report(report, args.conflict_report_format, output_encoding, |writer, format| write_conflict_report(&summary.key_columns, &summary.conflicts, writer, format));
+        if !summary.conflicts.is_empty() {
+            eprintln!("Warning: {} merged columns had conflicting values, see {}", summary.conflicts.len(), report);
+        }
+    }
+    if let Some(report) = &args.fuzzy_report {
+        write_report(report, args.fuzzy_report_format, output_encoding, |writer, format| write_fuzzy_report(&summary.key_columns, &summary.fuzzy_matches, writer, format));
+        if !summary.fuzzy_matches.is_empty() {
+            eprintln!("Warning: {} rows were matched to a different key by --fuzzy-keys, see {}", summary.fuzzy_matches.len(), report);
+        }
+    }
+    
+    // Only report success when it cannot end up mixed into the combined data
+    if let Some(output_file) = output_file {
+        println!("Successfully combined {} files into {}", args.input_files.len(), output_file);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_merge_strategy() {
+        let concat = |separator: &str| MergeStrategy::Concat(separator.to_string());
+        assert_eq!(parse_merge_strategy("max"), Ok((None, MergeStrategy::Max)));
+        assert_eq!(parse_merge_strategy("salary=max"), Ok((Some("salary".to_string()), MergeStrategy::Max)));
+        assert_eq!(parse_merge_strategy("time:utc=last-wins"), Ok((Some("time:utc".to_string()), MergeStrategy::LastWins)), "Column names may contain ':'");
+        assert_eq!(parse_merge_strategy("a=b=min"), Ok((Some("a=b".to_string()), MergeStrategy::Min)), "Column names may contain '='");
+        assert_eq!(parse_merge_strategy("concat:a=b"), Ok((None, concat("a=b"))));
+        assert_eq!(parse_merge_strategy("time:utc=concat: = "), Ok((Some("time:utc".to_string()), concat(" = "))));
+        assert_eq!(parse_merge_strategy("notes=concat"), Ok((Some("notes".to_string()), concat(";"))));
+        assert!(parse_merge_strategy("salary=").is_err());
+        assert!(parse_merge_strategy("time:utc").is_err());
+    }
 }
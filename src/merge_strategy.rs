@@ -0,0 +1,228 @@
+use std::fmt;
+use std::str::FromStr;
+
+/// Separator used by `concat` when none is given
+pub const DEFAULT_CONCAT_SEPARATOR: &str = ";";
+
+/// How a column's values are combined when rows with the same key are merged
+///
+/// Only values a row actually has count: a file without the column never overwrites it.
+/// Empty values (`""` or the empty value) are only kept when nothing else is available.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum MergeStrategy {
+    /// Keep the first non-empty value
+    #[default]
+    FirstWins,
+    /// Take the value from the last row that has the column, even if it is empty
+    LastWins,
+    /// Keep the longest value, the earlier one on a tie
+    PreferLongest,
+    /// Take the last non-empty value
+    PreferNonEmptyLatest,
+    /// Join the distinct non-empty values in the order seen
+    Concat(String),
+    /// Keep the largest number
+    Max,
+    /// Keep the smallest number
+    Min,
+    /// Add the numbers up
+    Sum,
+}
+
+pub(crate) fn is_empty(value: &str, empty_value: &str) -> bool {
+    value.is_empty() || value == empty_value
+}
+
+fn parse_number(value: &str) -> Option<f64> {
+    value.trim().parse::<f64>().ok().filter(|number| number.is_finite())
+}
+
+/// Digits after the decimal point, none for numbers written with an exponent
+fn decimal_places(value: &str) -> Option<usize> {
+    let value = value.trim();
+    if value.contains(['e', 'E']) {
+        return None;
+    }
+    Some(value.split_once('.').map_or(0, |(_, fraction)| fraction.len()))
+}
+
+impl MergeStrategy {
+    /// Merges a value from a later row into the value kept so far.  Numeric strategies fail on values that are not numbers
+    pub fn merge(&self, existing: &mut String, value: String, empty_value: &str) -> Result<(), String> {
+        if let MergeStrategy::LastWins = self {
+            *existing = value;
+            return Ok(());
+        }
+        if is_empty(&value, empty_value) {
+            return Ok(());
+        }
+        if is_empty(existing, empty_value) {
+            if self.is_numeric() && parse_number(&value).is_none() {
+                return Err(format!("non-numeric value '{}' cannot be merged with {}", value, self));
+            }
+            *existing = value;
+            return Ok(());
+        }
+        match self {
+            MergeStrategy::FirstWins | MergeStrategy::LastWins => {}
+            MergeStrategy::PreferLongest => {
+                if value.chars().count() > existing.chars().count() {
+                    *existing = value;
+                }
+            }
+            MergeStrategy::PreferNonEmptyLatest => *existing = value,
+            MergeStrategy::Concat(separator) => {
+                if !existing.split(separator.as_str()).any(|x| x == value) {
+                    existing.push_str(separator);
+                    existing.push_str(&value);
+                }
+            }
+            MergeStrategy::Max | MergeStrategy::Min | MergeStrategy::Sum => {
+                let (Some(x), Some(y)) = (parse_number(existing), parse_number(&value)) else {
+                    let bad_value = if parse_number(existing).is_none() { existing.clone() } else { value };
+                    return Err(format!("non-numeric value '{}' cannot be merged with {}", bad_value, self));
+                };
+                match self {
+                    MergeStrategy::Max if y > x => *existing = value,
+                    MergeStrategy::Min if y < x => *existing = value,
+                    MergeStrategy::Sum => {
+                        //whole numbers stay whole, anything else is added as floating point and rounded to the most
+                        //decimal places of the two, so 0.1 + 0.2 is 0.3
+                        *existing = match (existing.trim().parse::<i64>(), value.trim().parse::<i64>()) {
+                            (Ok(a), Ok(b)) if a.checked_add(b).is_some() => (a + b).to_string(),
+                            _ => match (decimal_places(existing), decimal_places(&value)) {
+                                (Some(a), Some(b)) => format!("{:.*}", a.max(b), x + y),
+                                _ => (x + y).to_string(),
+                            },
+                        };
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    /// Like `merge`, but a non-empty value from a higher-priority file always replaces the kept value and a
+    /// lower-priority one only fills it when empty.  The strategy only decides between values of the same priority
+    pub fn merge_with_priority(&self, existing: &mut String, existing_priority: &mut i32, value: String, priority: i32, empty_value: &str) -> Result<(), String> {
+        if priority == *existing_priority {
+            return self.merge(existing, value, empty_value);
+        }
+        if !is_empty(&value, empty_value) && (priority > *existing_priority || is_empty(existing, empty_value)) {
+            *existing = value;
+            *existing_priority = priority;
+        }
+        Ok(())
+    }
+
+    fn is_numeric(&self) -> bool {
+        matches!(self, MergeStrategy::Max | MergeStrategy::Min | MergeStrategy::Sum)
+    }
+}
+
+impl fmt::Display for MergeStrategy {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            MergeStrategy::FirstWins => write!(f, "first-wins"),
+            MergeStrategy::LastWins => write!(f, "last-wins"),
+            MergeStrategy::PreferLongest => write!(f, "prefer-longest"),
+            MergeStrategy::PreferNonEmptyLatest => write!(f, "prefer-non-empty-latest"),
+            MergeStrategy::Concat(separator) => write!(f, "concat:{}", separator),
+            MergeStrategy::Max => write!(f, "max"),
+            MergeStrategy::Min => write!(f, "min"),
+            MergeStrategy::Sum => write!(f, "sum"),
+        }
+    }
+}
+
+/// Parses the names shown by `Display`.  `concat` takes an optional separator after a colon, e.g. `concat:|`
+impl FromStr for MergeStrategy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if let Some(separator) = s.strip_prefix("concat:") {
+            if separator.is_empty() {
+                return Err("concat separator cannot be empty".to_string());
+            }
+            return Ok(MergeStrategy::Concat(separator.to_string()));
+        }
+        match s {
+            "first-wins" => Ok(MergeStrategy::FirstWins),
+            "last-wins" => Ok(MergeStrategy::LastWins),
+            "prefer-longest" => Ok(MergeStrategy::PreferLongest),
+            "prefer-non-empty-latest" => Ok(MergeStrategy::PreferNonEmptyLatest),
+            "concat" => Ok(MergeStrategy::Concat(DEFAULT_CONCAT_SEPARATOR.to_string())),
+            "max" => Ok(MergeStrategy::Max),
+            "min" => Ok(MergeStrategy::Min),
+            "sum" => Ok(MergeStrategy::Sum),
+            _ => Err(format!("unknown merge strategy '{}', expected first-wins, last-wins, prefer-longest, prefer-non-empty-latest, concat[:SEPARATOR], max, min or sum", s)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn merge_all(strategy: &MergeStrategy, values: &[&str]) -> Result<String, String> {
+        let mut existing = values[0].to_string();
+        for value in &values[1..] {
+            strategy.merge(&mut existing, value.to_string(), "EMPTY")?;
+        }
+        Ok(existing)
+    }
+
+    #[test]
+    fn test_merge_strategies() -> Result<(), String> {
+        let values = ["EMPTY", "Sales", "", "Engineering", "Sales"];
+        assert_eq!(merge_all(&MergeStrategy::FirstWins, &values)?, "Sales");
+        assert_eq!(merge_all(&MergeStrategy::LastWins, &values)?, "Sales");
+        assert_eq!(merge_all(&MergeStrategy::LastWins, &values[..3])?, "", "last-wins should take empty values too");
+        assert_eq!(merge_all(&MergeStrategy::PreferLongest, &values)?, "Engineering");
+        assert_eq!(merge_all(&MergeStrategy::PreferNonEmptyLatest, &values[..4])?, "Engineering");
+        assert_eq!(merge_all(&MergeStrategy::Concat(" | ".to_string()), &values)?, "Sales | Engineering", "concat should skip repeated values");
+
+        let numbers = ["EMPTY", "72000", "", "9.5e4", "68000"];
+        assert_eq!(merge_all(&MergeStrategy::Max, &numbers)?, "9.5e4", "max should keep the value as written");
+        assert_eq!(merge_all(&MergeStrategy::Min, &numbers)?, "68000");
+        assert_eq!(merge_all(&MergeStrategy::Sum, &["1", "2", "-4"])?, "-1");
+        assert_eq!(merge_all(&MergeStrategy::Sum, &["1", "2.5"])?, "3.5");
+        assert_eq!(merge_all(&MergeStrategy::Sum, &["0.1", "0.2"])?, "0.3", "sum should not show floating point error");
+        assert_eq!(merge_all(&MergeStrategy::Sum, &["19.99", "0.01", "5"])?, "25.00", "sum should keep the most decimal places");
+        assert_eq!(merge_all(&MergeStrategy::Sum, &["1.5", "1e1"])?, "11.5");
+        assert!(merge_all(&MergeStrategy::Sum, &["1", "n/a"]).is_err(), "Numeric strategies should reject text");
+        Ok(())
+    }
+
+    #[test]
+    fn test_merge_with_priority() -> Result<(), String> {
+        let mut existing = "Marketing".to_string();
+        let mut priority = 0;
+        MergeStrategy::FirstWins.merge_with_priority(&mut existing, &mut priority, "Sales".to_string(), 10, "")?;
+        assert_eq!((existing.as_str(), priority), ("Sales", 10), "Higher priority should win over first-wins");
+        MergeStrategy::LastWins.merge_with_priority(&mut existing, &mut priority, "HR".to_string(), 0, "")?;
+        assert_eq!(existing, "Sales", "Lower priority should not win over last-wins");
+        MergeStrategy::FirstWins.merge_with_priority(&mut existing, &mut priority, "".to_string(), 20, "")?;
+        assert_eq!(existing, "Sales", "Empty values should not replace anything");
+        MergeStrategy::Concat(";".to_string()).merge_with_priority(&mut existing, &mut priority, "Ops".to_string(), 10, "")?;
+        assert_eq!(existing, "Sales;Ops", "Same priority should use the strategy");
+
+        let mut existing = String::new();
+        let mut priority = 10;
+        MergeStrategy::FirstWins.merge_with_priority(&mut existing, &mut priority, "HR".to_string(), 0, "")?;
+        assert_eq!((existing.as_str(), priority), ("HR", 0), "Lower priority should fill an empty value");
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_merge_strategy() {
+        for name in ["first-wins", "last-wins", "prefer-longest", "prefer-non-empty-latest", "concat:;", "max", "min", "sum"] {
+            assert_eq!(name.parse::<MergeStrategy>().unwrap().to_string(), name);
+        }
+        assert_eq!("concat".parse::<MergeStrategy>(), Ok(MergeStrategy::Concat(";".to_string())));
+        assert_eq!("concat: / ".parse::<MergeStrategy>(), Ok(MergeStrategy::Concat(" / ".to_string())));
+        assert!("concat:".parse::<MergeStrategy>().is_err());
+        assert!("average".parse::<MergeStrategy>().is_err());
+    }
+}
@@ -0,0 +1,309 @@
+use std::fmt;
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::str::FromStr;
+
+use encoding_rs::{Decoder, DecoderResult, Encoder, EncoderResult, Encoding, UTF_16BE, UTF_16LE, UTF_8, WINDOWS_1252};
+
+/// Character encoding of an input
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub enum TextEncoding {
+    /// A byte order mark if there is one, otherwise UTF-16 if every other byte of the start is zero, UTF-8 if
+    /// the start is valid UTF-8 and Windows-1252 if it is not, see `detect_encoding`.  A start that is only ASCII
+    /// leaves UTF-8 or Windows-1252 to the first non-ASCII character
+    #[default]
+    Auto,
+    Named(&'static Encoding),
+}
+
+impl fmt::Display for TextEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TextEncoding::Auto => write!(f, "auto"),
+            TextEncoding::Named(encoding) => write!(f, "{}", encoding.name().to_ascii_lowercase()),
+        }
+    }
+}
+
+/// Parses `auto` or an encoding label, e.g. utf-8, utf-16le, windows-1252 or latin1 (read as Windows-1252)
+impl FromStr for TextEncoding {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        if s == "auto" {
+            return Ok(TextEncoding::Auto);
+        }
+        Encoding::for_label(s.as_bytes())
+            .map(TextEncoding::Named)
+            .ok_or_else(|| format!("unknown encoding '{}', expected auto or a label like utf-8, utf-16le, windows-1252 or latin1", s))
+    }
+}
+
+/// Parses an encoding label for the output.  UTF-16 is not offered, the output has to be ASCII-compatible
+pub fn parse_output_encoding(s: &str) -> Result<&'static Encoding, String> {
+    match s.parse::<TextEncoding>()? {
+        TextEncoding::Named(encoding) if encoding.is_ascii_compatible() => Ok(encoding),
+        TextEncoding::Named(encoding) => Err(format!("{} cannot be written, the output encoding must be ASCII-compatible like utf-8 or windows-1252", encoding.name())),
+        TextEncoding::Auto => Err("the output encoding cannot be auto".to_string()),
+    }
+}
+
+/// Works out the encoding of an input from its first bytes
+///
+/// A byte order mark wins.  Without one, mostly-ASCII UTF-16 shows up as a zero in every other byte, and anything
+/// else is UTF-8 if it is valid UTF-8 (a character cut off at the end of `sample` is allowed) or Windows-1252 if not
+pub fn detect_encoding(sample: &[u8]) -> &'static Encoding {
+    if let Some((encoding, _)) = Encoding::for_bom(sample) {
+        return encoding;
+    }
+    let pairs = sample.len() / 2;
+    let zeros_at = |offset: usize| sample.iter().skip(offset).step_by(2).filter(|&&byte| byte == 0).count();
+    if pairs > 0 && zeros_at(1) * 2 > pairs {
+        return UTF_16LE;
+    }
+    if pairs > 0 && zeros_at(0) * 2 > pairs {
+        return UTF_16BE;
+    }
+    match std::str::from_utf8(sample) {
+        Err(e) if e.error_len().is_some() => WINDOWS_1252,
+        _ => UTF_8,
+    }
+}
+
+/// Turns an input into UTF-8, detecting its encoding from the bytes `reader` has buffered with `TextEncoding::Auto`.
+/// A byte order mark of the encoding is dropped.  Also returns the encoding, to open the same input again without detecting.
+///
+/// When everything buffered is ASCII the choice between UTF-8 and Windows-1252 waits for the first non-ASCII byte,
+/// however far into the input, and `TextEncoding::Auto` is returned so the input is detected again when reopened
+pub(crate) fn decode_input<R: BufRead + Send + 'static>(mut reader: R, encoding: TextEncoding) -> io::Result<(Box<dyn BufRead + Send>, TextEncoding)> {
+    let encoding = match encoding {
+        TextEncoding::Auto => {
+            let sample = reader.fill_buf()?;
+            let encoding = detect_encoding(sample);
+            if encoding == UTF_8 && sample.is_ascii() {
+                let decoder = DecodingReader { reader, decoder: None, pending: Vec::new(), decoded: Vec::new(), done: false };
+                return Ok((Box::new(BufReader::new(decoder)), TextEncoding::Auto));
+            }
+            encoding
+        }
+        TextEncoding::Named(encoding) => encoding,
+    };
+    if encoding == UTF_8 {
+        //already what the reader expects, only the byte order mark has to go
+        if reader.fill_buf()?.starts_with(b"\xEF\xBB\xBF") {
+            reader.consume(3);
+        }
+        return Ok((Box::new(reader), TextEncoding::Named(encoding)));
+    }
+    let decoder = DecodingReader { reader, decoder: Some(encoding.new_decoder_with_bom_removal()), pending: Vec::new(), decoded: Vec::new(), done: false };
+    Ok((Box::new(BufReader::new(decoder)), TextEncoding::Named(encoding)))
+}
+
+struct DecodingReader<R: BufRead> {
+    reader: R,
+    decoder: Option<Decoder>, //none while only ASCII has been read and the encoding is still open
+    pending: Vec<u8>, //bytes read to make up the encoding, decoded before anything else
+    decoded: Vec<u8>, //rest of a character that did not fit the caller's buffer
+    done: bool,
+}
+
+impl<R: BufRead> DecodingReader<R> {
+    /// Picks UTF-8 if the first non-ASCII character is valid UTF-8, Windows-1252 if not
+    fn choose_decoder(&mut self) -> io::Result<Decoder> {
+        while self.pending.len() < 4 { //the longest UTF-8 sequence
+            let src = self.reader.fill_buf()?;
+            if src.is_empty() {
+                break;
+            }
+            let take = src.len().min(4 - self.pending.len());
+            self.pending.extend_from_slice(&src[..take]);
+            self.reader.consume(take);
+        }
+        let is_utf8 = match std::str::from_utf8(&self.pending) {
+            Ok(_) => true,
+            Err(e) => e.valid_up_to() > 0,
+        };
+        Ok(if is_utf8 { UTF_8 } else { WINDOWS_1252 }.new_decoder_without_bom_handling())
+    }
+}
+
+impl<R: BufRead> Read for DecodingReader<R> {
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        if !self.decoded.is_empty() {
+            let len = buf.len().min(self.decoded.len());
+            buf[..len].copy_from_slice(&self.decoded[..len]);
+            self.decoded.drain(..len);
+            return Ok(len);
+        }
+        while !self.done {
+            let decoder = match &mut self.decoder {
+                Some(decoder) => decoder,
+                None => {
+                    let src = self.reader.fill_buf()?;
+                    let ascii_len = src.iter().position(|byte| !byte.is_ascii()).unwrap_or(src.len()).min(buf.len());
+                    if ascii_len > 0 || src.is_empty() {
+                        buf[..ascii_len].copy_from_slice(&src[..ascii_len]);
+                        self.reader.consume(ascii_len);
+                        return Ok(ascii_len);
+                    }
+                    let decoder = self.choose_decoder()?;
+                    self.decoder.insert(decoder)
+                }
+            };
+            let from_pending = !self.pending.is_empty();
+            let src = if from_pending { &self.pending[..] } else { self.reader.fill_buf()? };
+            let last = src.is_empty();
+            let (result, read, written) = decoder.decode_to_utf8_without_replacement(src, buf, last);
+            if from_pending {
+                self.pending.drain(..read);
+            } else {
+                self.reader.consume(read);
+            }
+            match result {
+                DecoderResult::Malformed(_, _) => {
+                    let message = format!("input is not valid {}", decoder.encoding().name());
+                    return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+                }
+                DecoderResult::InputEmpty => self.done = last,
+                DecoderResult::OutputFull if written == 0 => {
+                    //a decoded character takes up to four bytes, the rest is kept for the next read
+                    let mut scratch = [0; 4];
+                    let written = self.read(&mut scratch)?;
+                    self.decoded.extend_from_slice(&scratch[..written]);
+                    return self.read(buf);
+                }
+                DecoderResult::OutputFull => {}
+            }
+            if written > 0 {
+                return Ok(written);
+            }
+        }
+        Ok(0)
+    }
+}
+
+/// Writes UTF-8 text in another ASCII-compatible encoding.  A character the encoding does not have is an error
+pub struct EncodingWriter<W: Write> {
+    writer: W,
+    encoder: Encoder,
+    pending: Vec<u8>, //start of a character whose remaining bytes have not been written yet
+}
+
+impl<W: Write> EncodingWriter<W> {
+    pub fn new(writer: W, encoding: &'static Encoding) -> Self {
+        EncodingWriter { writer, encoder: encoding.new_encoder(), pending: Vec::new() }
+    }
+}
+
+impl<W: Write> Write for EncodingWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.encoder.encoding() == UTF_8 {
+            return self.writer.write(buf);
+        }
+        self.pending.extend_from_slice(buf);
+        let complete = match std::str::from_utf8(&self.pending) {
+            Ok(text) => text.len(),
+            Err(e) if e.error_len().is_none() => e.valid_up_to(),
+            Err(e) => return Err(io::Error::new(io::ErrorKind::InvalidData, e)),
+        };
+        let mut text = std::str::from_utf8(&self.pending[..complete]).unwrap();
+        let mut encoded: Vec<u8> = Vec::new();
+        while !text.is_empty() {
+            encoded.reserve(self.encoder.max_buffer_length_from_utf8_without_replacement(text.len()).unwrap_or(text.len()));
+            let (result, read) = self.encoder.encode_from_utf8_to_vec_without_replacement(text, &mut encoded, false);
+            if let EncoderResult::Unmappable(char) = result {
+                let message = format!("'{}' cannot be written in {}", char, self.encoder.encoding().name());
+                return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+            }
+            text = &text[read..];
+        }
+        self.writer.write_all(&encoded)?;
+        self.pending.drain(..complete);
+        Ok(buf.len())
+    }
+
+    /// Fails if the text written so far ends in the middle of a character, whose bytes would otherwise be lost
+    fn flush(&mut self) -> io::Result<()> {
+        if !self.pending.is_empty() {
+            let message = format!("output ends with an incomplete UTF-8 sequence {:02X?}", self.pending);
+            return Err(io::Error::new(io::ErrorKind::InvalidData, message));
+        }
+        self.writer.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn decode(bytes: &'static [u8], encoding: TextEncoding) -> io::Result<(String, TextEncoding)> {
+        decode_reader(bytes, encoding)
+    }
+
+    fn decode_reader(reader: impl BufRead + Send + 'static, encoding: TextEncoding) -> io::Result<(String, TextEncoding)> {
+        let (mut reader, encoding) = decode_input(reader, encoding)?;
+        let mut text = String::new();
+        reader.read_to_string(&mut text)?;
+        Ok((text, encoding))
+    }
+
+    #[test]
+    fn test_decode_input() -> io::Result<()> {
+        assert_eq!(decode(b"\xEF\xBB\xBFid,name\n", TextEncoding::Auto)?, ("id,name\n".to_string(), TextEncoding::Named(UTF_8)), "A UTF-8 byte order mark should be dropped");
+        assert_eq!(decode(b"\xFF\xFEi\0d\0\n\0", TextEncoding::Auto)?, ("id\n".to_string(), TextEncoding::Named(UTF_16LE)));
+        assert_eq!(decode(b"i\0d\0,\0\xE9\0\n\0", TextEncoding::Auto)?, ("id,\u{e9}\n".to_string(), TextEncoding::Named(UTF_16LE)), "UTF-16 without a byte order mark should be found");
+        assert_eq!(decode(b"\0i\0d", TextEncoding::Auto)?.1, TextEncoding::Named(UTF_16BE));
+        assert_eq!(decode(b"Ren\xE9,\x80 5\n", TextEncoding::Auto)?, ("Ren\u{e9},\u{20ac} 5\n".to_string(), TextEncoding::Named(WINDOWS_1252)));
+        assert_eq!(detect_encoding("Ren\u{e9}".as_bytes()), UTF_8);
+        assert_eq!(detect_encoding(&"Ren\u{e9}".as_bytes()[..4]), UTF_8, "A character cut off by the sample should still be UTF-8");
+
+        assert_eq!(decode(b"Ren\xE9", "latin1".parse().unwrap())?.0, "Ren\u{e9}");
+        assert!(decode(b"\xFF\xFE\0\xD8", "utf-16le".parse().unwrap()).is_err(), "Malformed input should be an error");
+        assert!("ebcdic".parse::<TextEncoding>().is_err());
+        Ok(())
+    }
+
+    #[test]
+    fn test_late_non_ascii() -> io::Result<()> {
+        //the first non-ASCII byte comes after the first buffer, so the encoding is only chosen once it is reached
+        let ascii = "1,Ana\n".repeat(2000);
+        let windows_1252 = [ascii.as_bytes(), b"2,Ren\xE9\n"].concat();
+        assert!(windows_1252.len() > 8192);
+        assert_eq!(decode_reader(BufReader::new(io::Cursor::new(windows_1252)), TextEncoding::Auto)?, (format!("{}2,Ren\u{e9}\n", ascii), TextEncoding::Auto));
+        let utf8 = format!("{}2,Ren\u{e9}\n", ascii);
+        assert_eq!(decode_reader(BufReader::new(io::Cursor::new(utf8.clone().into_bytes())), TextEncoding::Auto)?.0, utf8);
+
+        //a character split across two buffers is still seen whole
+        let split = BufReader::with_capacity(8, io::Cursor::new("1,Ana\n\u{e9}\u{e9}\n".as_bytes()));
+        assert_eq!(decode_reader(split, TextEncoding::Auto)?.0, "1,Ana\n\u{e9}\u{e9}\n");
+        let split = BufReader::with_capacity(8, io::Cursor::new(&b"1,Ana\n\xE9\xE9\n"[..]));
+        assert_eq!(decode_reader(split, TextEncoding::Auto)?.0, "1,Ana\n\u{e9}\u{e9}\n");
+        let truncated = BufReader::with_capacity(6, io::Cursor::new(&b"1,Ana\n\xC3"[..]));
+        assert_eq!(decode_reader(truncated, TextEncoding::Auto)?.0, "1,Ana\n\u{c3}", "A cut-off UTF-8 start at the end should be Windows-1252");
+
+        let mixed = [ascii.as_bytes(), "\u{e9}\n".as_bytes(), b"\xE9\n"].concat();
+        assert!(decode_reader(BufReader::new(io::Cursor::new(mixed)), TextEncoding::Auto).is_err(), "Invalid UTF-8 after valid UTF-8 should still fail");
+        Ok(())
+    }
+
+    #[test]
+    fn test_encoding_writer() -> io::Result<()> {
+        let mut buffer: Vec<u8> = Vec::new();
+        let mut writer = EncodingWriter::new(&mut buffer, WINDOWS_1252);
+        let text = "Ren\u{e9} \u{20ac}5\n".as_bytes();
+        writer.write_all(&text[..4])?; //splits the é
+        writer.write_all(&text[4..])?;
+        writer.flush()?;
+        assert_eq!(buffer, b"Ren\xE9 \x805\n");
+
+        let mut writer = EncodingWriter::new(Vec::new(), WINDOWS_1252);
+        writer.write_all(&text[..4])?;
+        assert_eq!(writer.flush().map_err(|e| e.kind()), Err(io::ErrorKind::InvalidData), "A character cut off at the end should not be dropped");
+
+        let mut writer = EncodingWriter::new(Vec::new(), WINDOWS_1252);
+        assert!(writer.write_all("\u{141}\u{f3}d\u{17a}".as_bytes()).is_err(), "Characters outside the encoding should be an error");
+        assert_eq!(parse_output_encoding("latin1"), Ok(WINDOWS_1252));
+        assert!(parse_output_encoding("utf-16le").is_err());
+        assert!(parse_output_encoding("auto").is_err());
+        Ok(())
+    }
+}
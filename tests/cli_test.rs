@@ -1,5 +1,6 @@
-use std::process::Command;
+use std::process::{Command, Stdio};
 use std::fs;
+use std::io::Write;
 use std::path::Path;
 
 // Helper function to run the CLI binary
@@ -87,8 +88,8 @@ fn test_cli_level3_missing_columns() {
     assert!(lines[0].contains("gender"), "Header should include 'gender' column");
     
     // Check that first 5 rows have EMPTY for gender
-    for i in 1..6 {
-        assert!(lines[i].contains("EMPTY"), "Missing fields should have EMPTY value");
+    for line in &lines[1..6] {
+        assert!(line.contains("EMPTY"), "Missing fields should have EMPTY value");
     }
     
     cleanup(output);
@@ -171,8 +172,8 @@ fn test_cli_merge_duplicates() {
     assert_eq!(lines.len(), 6, "Output should have 6 lines (1 header + 5 merged rows)");
     
     // Verify no EMPTY values in data rows (all should be filled from merge)
-    for i in 1..lines.len() {
-        assert!(!lines[i].contains("EMPTY"), "Merged rows should not contain EMPTY values");
+    for line in &lines[1..] {
+        assert!(!line.contains("EMPTY"), "Merged rows should not contain EMPTY values");
     }
     
     cleanup(output);
@@ -201,7 +202,83 @@ fn test_cli_custom_delimiter() {
 }
 
 #[test]
-fn test_cli_error_no_output() {
+fn test_cli_quote_style_always() {
+    let output = "csv_testing_output/test_cli_output_quote_always.csv";
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees5.csv",
+        "-o", output,
+        "--quote-style", "always",
+    ]);
+    
+    assert!(result.is_ok(), "CLI command should succeed");
+    let output_result = result.unwrap();
+    assert!(output_result.status.success(), "Command should exit successfully");
+    
+    let contents = fs::read_to_string(output).expect("Should read output file");
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines[0], "\"id\",\"name\",\"department\",\"salary\",\"address\"", "Header should be quoted");
+    assert_eq!(lines[9], "\"24\",\"Smith, Jr., Robert\",\"Engineering\",\"99000\",\"321 Elm St, Building \"\"A\"\"\"", "Embedded quotes should be doubled");
+    
+    cleanup(output);
+}
+
+#[test]
+fn test_cli_unterminated_quote() {
+    let output = "csv_testing_output/test_cli_output_unterminated.csv";
+    
+    // Without --lenient the run fails with the position of the bad field
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees8.csv",
+        "-o", output,
+    ]);
+    let output_result = result.expect("Command should run");
+    assert!(!output_result.status.success(), "Command should fail on unterminated quote");
+    let stderr = String::from_utf8_lossy(&output_result.stderr);
+    assert!(stderr.contains("csv_samples/employees8.csv:3:34"), "Error should name file, line and column: {}", stderr);
+    
+    // With --lenient the field is closed at end of file and a warning is printed
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees8.csv",
+        "-o", output,
+        "--lenient",
+    ]);
+    let output_result = result.expect("Command should run");
+    assert!(output_result.status.success(), "Command should succeed in lenient mode");
+    let stderr = String::from_utf8_lossy(&output_result.stderr);
+    assert!(stderr.contains("Warning: csv_samples/employees8.csv:3:34"), "Warning should be reported: {}", stderr);
+    
+    cleanup(output);
+}
+
+#[test]
+fn test_cli_ragged_rows_spill() {
+    let output = "csv_testing_output/test_cli_output_ragged.csv";
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees9.csv",
+        "-o", output,
+        "--ragged-rows", "spill",
+    ]);
+    
+    let output_result = result.expect("Command should run");
+    assert!(output_result.status.success(), "Command should exit successfully");
+    let stderr = String::from_utf8_lossy(&output_result.stderr);
+    assert!(stderr.contains("3 rows had a different number of fields"), "Ragged rows should be counted: {}", stderr);
+    
+    let contents = fs::read_to_string(output).expect("Should read output file");
+    let first_line = contents.lines().next().unwrap();
+    assert_eq!(first_line, "id,name,department,salary,_extra_1,_extra_2", "Extra fields should get their own columns");
+    
+    cleanup(output);
+}
+
+#[test]
+fn test_cli_no_output_writes_stdout() {
     let result = run_cli(&[
         "csv_samples/employees1.csv",
         "csv_samples/employees2.csv",
@@ -209,7 +286,486 @@ fn test_cli_error_no_output() {
     
     assert!(result.is_ok(), "Command should run");
     let output = result.unwrap();
-    assert!(!output.status.success(), "Command should fail without output file");
+    assert!(output.status.success(), "Command should write to stdout without an output file");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 11, "Stdout should only hold the combined data (1 header + 10 data rows)");
+    assert_eq!(stdout.lines().next(), Some("id,name,department,salary"));
+}
+
+#[test]
+fn test_cli_dash_output_writes_stdout() {
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "-o", "-",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    assert!(!Path::new("-").exists(), "No file named '-' should be created");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 6, "Stdout should hold 1 header + 5 data rows");
+}
+
+#[test]
+fn test_cli_stdin_input() {
+    let mut child = Command::new("cargo")
+        .args(["run", "--quiet", "--", "csv_samples/employees1.csv", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Command should run");
+    child.stdin.take().unwrap().write_all(b"id,name,gender\n50,Kim Yu,F\n").unwrap();
+    let output = child.wait_with_output().expect("Command should finish");
+    
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "id,name,department,salary,gender");
+    assert_eq!(lines[6], "50,Kim Yu,,,F", "Stdin rows should follow the file's rows");
+}
+
+#[test]
+fn test_cli_merge_strategy() {
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees1_update.csv",
+        "--keys", "id",
+        "--merge-duplicates",
+        "--merge-strategy", "prefer-non-empty-latest",
+        "--merge-strategy", "salary=max",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[2], "2,Bob Smith,Sales,72000,moved teams");
+    assert_eq!(lines[4], "4,David Brown,Sales,69000,");
+    
+    // Strategies only apply when merging
+    let result = run_cli(&["csv_samples/employees1.csv", "--merge-strategy", "sum"]);
+    assert_eq!(result.expect("Command should run").status.code(), Some(1), "--merge-strategy without --merge-duplicates should exit with code 1");
+}
+
+#[test]
+fn test_cli_conflict_report() {
+    let output = "csv_testing_output/test_cli_output_conflicts.csv";
+    let report = "csv_testing_output/test_cli_conflicts.json";
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees1_update.csv",
+        "-o", output,
+        "--keys", "id",
+        "--merge-duplicates",
+        "--conflict-report", report,
+    ]);
+    
+    let output_result = result.expect("Command should run");
+    assert!(output_result.status.success(), "Conflicts alone should not fail the run");
+    let contents = fs::read_to_string(report).expect("Report should be written");
+    assert_eq!(contents.lines().count(), 5, "Report should hold 3 conflicts between the brackets");
+    assert!(contents.contains(r#"{"key": {"id": "4"}, "column": "salary", "candidates": [{"value": "65000", "file": "csv_samples/employees1.csv", "line": 5}"#));
+    
+    fs::write(output, "keep\n").expect("Output should be writable");
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees1_update.csv",
+        "-o", output,
+        "--keys", "id",
+        "--merge-duplicates",
+        "--fail-on-conflict",
+    ]);
+    
+    let output_result = result.expect("Command should run");
+    assert_eq!(output_result.status.code(), Some(8), "Conflicts should exit with code 8");
+    let stderr = String::from_utf8_lossy(&output_result.stderr);
+    assert!(stderr.contains("3 merge conflicts"), "Got: {}", stderr);
+    assert_eq!(fs::read_to_string(output).expect("Output should still exist"), "keep\n", "A failed run should leave the existing output alone");
+    assert!(!Path::new(&format!("{}.tmp", output)).exists(), "The temporary output should be removed");
+    
+    cleanup(output);
+    cleanup(report);
+}
+
+#[test]
+fn test_cli_join() {
+    let result = run_cli(&[
+        "csv_samples/employees1_name.csv",
+        "csv_samples/employees7.csv",
+        "--keys", "id",
+        "--merge-duplicates",
+        "--join", "anti",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let ids: Vec<&str> = stdout.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+    assert_eq!(ids, ["4", "5"], "Only employees missing from the second file should be listed");
+    
+    let result = run_cli(&["csv_samples/employees1.csv", "csv_samples/employees7.csv", "--join", "inner"]);
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(1), "--join without --merge-duplicates should be a usage error");
+}
+
+#[test]
+fn test_cli_set_op() {
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees7.csv",
+        "--remove-duplicates",
+        "--set-op", "symmetric-difference",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let ids: Vec<&str> = stdout.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+    assert_eq!(ids, ["4", "5", "31", "32", "33"], "Only rows in exactly one file should be listed");
+    
+    let result = run_cli(&["csv_samples/employees1.csv", "csv_samples/employees7.csv", "--set-op", "intersect"]);
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(1), "--set-op without --remove-duplicates should be a usage error");
+}
+
+#[test]
+fn test_cli_diff() {
+    let output = "csv_testing_output/test_cli_diff.json";
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees14.csv",
+        "-o", output,
+        "--keys", "id",
+        "--diff",
+    ]);
+    
+    let output_result = result.expect("Command should run");
+    assert!(output_result.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output_result.stdout);
+    assert!(stdout.contains("1 rows added, 1 removed and 2 changed"), "Got: {}", stdout);
+    let contents = fs::read_to_string(output).expect("Diff should be written");
+    assert_eq!(contents.lines().nth(1), Some(r#"  {"change": "changed", "key": {"id": "2"}, "columns": {"department": {"old": "Marketing", "new": "Sales"}}},"#),
+        "A .json output should get JSON");
+    
+    let result = run_cli(&["csv_samples/employees1.csv", "--keys", "id", "--diff"]);
+    let output_result = result.expect("Command should run");
+    assert_eq!(output_result.status.code(), Some(1), "A diff of one input should be a usage error");
+    
+    let result = run_cli(&["csv_samples/employees1.csv", "csv_samples/employees14.csv", "--diff"]);
+    let output_result = result.expect("Command should run");
+    assert_eq!(output_result.status.code(), Some(1), "A diff without --keys should be a usage error");
+    let stderr = String::from_utf8_lossy(&output_result.stderr);
+    assert!(stderr.contains("--diff requires --keys"));
+    
+    cleanup(output);
+}
+
+#[test]
+fn test_cli_diff_output_dialect() {
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees14.csv",
+        "--keys", "id",
+        "--diff",
+        "--output-delimiter", ";",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().take(2).collect::<Vec<_>>(), ["change;id;column;old;new", "changed;2;department;Marketing;Sales"],
+        "A CSV diff should use the output delimiter");
+}
+
+#[test]
+fn test_cli_delimiter_auto() {
+    let result = run_cli(&[
+        "csv_samples/employees15.tsv",
+        "csv_samples/employees16.csv",
+        "--delimiter", "auto",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines, ["id,name,department,salary", "42,Hiro Tanaka,Engineering,91000", "43,Lena Fischer,Sales,67000",
+        "44,Dubois; Claire,Finance,\"71000,50\"", "45,Marco Rossi,Marketing,69000"], "Output should use the comma default");
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees15.tsv",
+        "--file-delimiter", "csv_samples/employees15.tsv=\t",
+    ]);
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().count(), 8, "Should have 1 header + 7 data rows");
+}
+
+#[test]
+fn test_cli_file_dialect() {
+    let result = run_cli(&[
+        "csv_samples/employees16.csv",
+        "csv_samples/employees20.tsv",
+        "--file-dialect", "csv_samples/employees16.csv=semicolon",
+        "--file-dialect", "csv_samples/employees20.tsv=tab,',noheader",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[0], "id,name,department,salary,column_1,column_2,column_3,column_4", "The headerless file's columns should be named by position");
+    assert_eq!(lines[1], "44,Dubois; Claire,Finance,\"71000,50\",,,,");
+    assert_eq!(lines[3], ",,,,50,Chen\tWei,Support,61000", "A tab inside ' quotes should stay in the field");
+    assert_eq!(lines[4], ",,,,51,\"Sam \"\"Sparky\"\" Lee\",Engineering,88000", "\" should be an ordinary character in the TSV");
+    
+    let result = run_cli(&["csv_samples/employees20.tsv", "--file-dialect", "csv_samples/employees20.tsv=tab,',noheader,header"]);
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(2), "A dialect with two header settings should be rejected");
+}
+
+#[test]
+fn test_cli_convert_delimiters() {
+    let result = run_cli(&[
+        "csv_samples/employees16.csv",
+        "--input-delimiter", ";",
+        "--output-delimiter", "\t",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout, "id\tname\tdepartment\tsalary\n44\tDubois; Claire\tFinance\t71000,50\n45\tMarco Rossi\tMarketing\t69000\n");
+    
+    let result = run_cli(&[
+        "csv_samples/employees16.csv",
+        "-d", ";",
+        "--output-quote", "'",
+        "--quote-style", "non-numeric",
+    ]);
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert!(stdout.contains("44;'Dubois; Claire';'Finance';'71000,50'"), "-d should still set the output delimiter, got: {}", stdout);
+}
+
+#[test]
+fn test_cli_delimiter_escapes() {
+    let result = run_cli(&[
+        "csv_samples/employees15.tsv",
+        "-d", "\\t",
+        "--output-delimiter", "pipe",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().next(), Some("id|name|department|salary"), "\\t should read tabs");
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "--output-delimiter", "'",
+        "--output-quote", "'",
+    ]);
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(1), "A delimiter that is also the quote character should be a config error");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("output delimiter '\\'' is also the output quote character"), "Got: {}", stderr);
+}
+
+#[test]
+fn test_cli_encodings() {
+    let result = run_cli(&[
+        "csv_samples/employees18.csv",
+        "csv_samples/employees19.csv",
+        "--output-encoding", "windows-1252",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    assert!(output.stdout.ends_with(b"49,Ana\xEFs Lef\xE8vre,Marketing,71000\n"), "UTF-16 rows should be written as Windows-1252");
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees18.csv",
+        "--encoding", "csv_samples/employees18.csv=utf-8",
+    ]);
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(4), "Reading Windows-1252 as UTF-8 should be an I/O error");
+    
+    let result = run_cli(&["csv_samples/employees1.csv", "--output-encoding", "utf-16le"]);
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(2), "UTF-16 output should be rejected");
+    
+    let report = "csv_testing_output/test_cli_encoded_conflicts.csv";
+    let mut child = Command::new("cargo")
+        .args(["run", "--quiet", "--", "csv_samples/employees18.csv", "-", "--keys", "id", "--merge-duplicates"])
+        .args(["--conflict-report", report, "--output-encoding", "windows-1252"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .spawn()
+        .expect("Command should run");
+    child.stdin.take().unwrap().write_all("id,name\n46,Ren\u{e9} Mueller\n".as_bytes()).unwrap();
+    let output = child.wait_with_output().expect("Command should finish");
+    assert!(output.status.success(), "Command should exit successfully");
+    let contents = fs::read(report).expect("Report should be written");
+    assert!(contents.windows(11).any(|w| w == b"Ren\xE9 M\xFCller"), "The conflict report should be written in the output encoding");
+    cleanup(report);
+}
+
+#[test]
+fn test_cli_fuzzy_keys() {
+    let report = "csv_testing_output/test_cli_fuzzy_matches.csv";
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees13.csv",
+        "--keys", "name",
+        "--merge-duplicates",
+        "--fuzzy-keys", "jaro-winkler:0.9",
+        "--fuzzy-report", report,
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    assert_eq!(stdout.lines().nth(1), Some("Alice Johnson,1,Engineering,95000,alice.johnson@example.com,555-0101"));
+    let contents = fs::read_to_string(report).expect("Report should be written");
+    let lines: Vec<&str> = contents.lines().collect();
+    assert_eq!(lines[0], "name,matched_name,file,line,score");
+    assert!(lines[1].starts_with("Alice Johnson,Alice Jonson,csv_samples/employees13.csv,2,0.9"), "Got: {}", lines[1]);
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("rows were matched to a different key"), "Got: {}", stderr);
+    
+    let result = run_cli(&["csv_samples/employees13.csv", "--keys", "name", "--fuzzy-keys", "levenshtein:1"]);
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(1), "--fuzzy-keys without -r or -m should be a usage error");
+    
+    cleanup(report);
+}
+
+#[test]
+fn test_cli_missing_key_columns() {
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees1_name.csv",
+        "--keys", "id,department",
+        "--remove-duplicates",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(7), "Missing key columns should exit with code 7");
+    let stderr = String::from_utf8_lossy(&output.stderr);
+    assert!(stderr.contains("csv_samples/employees1_name.csv lacks department"), "Got: {}", stderr);
+    assert!(output.stdout.is_empty(), "Nothing should be written");
+    
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees1_name.csv",
+        "--keys", "id,department",
+        "--key-presence", "any",
+        "--remove-duplicates",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "A key in one input should be enough with --key-presence any");
+}
+
+#[test]
+fn test_cli_normalize_key() {
+    let result = run_cli(&[
+        "csv_samples/employees12.csv",
+        "--keys", "id",
+        "--remove-duplicates",
+        "--normalize-key", "id=trim,numeric",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines, ["id,email,name", "42,ana.lopez@example.com,Ana L\u{f3}pez", "7,wei.chen@example.com,Wei Chen"]);
+}
+
+#[test]
+fn test_cli_keep() {
+    let result = run_cli(&[
+        "csv_samples/employees11.csv",
+        "--keys", "id",
+        "--remove-duplicates",
+        "--keep", "max:updated_at",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines.len(), 4, "Should have 1 header + 3 data rows");
+    assert_eq!(lines[1], "1,Alice Johnson,Platform,2024-06-30", "Newest record should survive");
+    assert_eq!(lines[2], "2,Bob Smith,Marketing,2024-03-02");
+    
+    let result = run_cli(&["csv_samples/employees11.csv", "--keep", "last"]);
+    assert_eq!(result.expect("Command should run").status.code(), Some(1), "--keep without --remove-duplicates should exit with code 1");
+}
+
+#[test]
+fn test_cli_priority() {
+    let result = run_cli(&[
+        "csv_samples/employees1.csv",
+        "csv_samples/employees1_update.csv",
+        "--keys", "id",
+        "--merge-duplicates",
+        "--priority", "csv_samples/employees1_update.csv=10",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let lines: Vec<&str> = stdout.lines().collect();
+    assert_eq!(lines[2], "2,Bob Smith,Sales,70000,moved teams", "Higher-priority values should win");
+    assert_eq!(lines[4], "4,David Brown,Sales,69000,", "Empty higher-priority values should not");
+    
+    let result = run_cli(&["csv_samples/employees1.csv", "-r", "--priority", "hr.csv=10"]);
+    let output = result.expect("Command should run");
+    assert_eq!(output.status.code(), Some(1), "Priority for a file that is not an input should exit with code 1");
+    assert!(String::from_utf8_lossy(&output.stderr).contains("'hr.csv', which is not an input"));
+}
+
+#[test]
+fn test_cli_sort_by() {
+    let result = run_cli(&[
+        "csv_samples/employees2.csv",
+        "csv_samples/employees1.csv",
+        "--sort-by", "department,salary",
+    ]);
+    
+    let output = result.expect("Command should run");
+    assert!(output.status.success(), "Command should exit successfully");
+    let stdout = String::from_utf8_lossy(&output.stdout);
+    let ids: Vec<&str> = stdout.lines().skip(1).map(|line| line.split(',').next().unwrap()).collect();
+    assert_eq!(ids, ["3", "6", "1", "9", "10", "5", "7", "2", "4", "8"], "Rows should be sorted by department, then salary");
+}
+
+#[test]
+fn test_cli_error_missing_input() {
+    let output = "csv_testing_output/test_cli_output_missing.csv";
+    
+    let result = run_cli(&[
+        "csv_samples/missing.csv",
+        "csv_samples/employees1.csv",
+        "-o", output,
+    ]);
+    
+    let output_result = result.expect("Command should run");
+    assert_eq!(output_result.status.code(), Some(3), "Missing input should exit with code 3");
+    let stderr = String::from_utf8_lossy(&output_result.stderr);
+    assert!(stderr.contains("Error: Input file 'csv_samples/missing.csv' not found"), "Unexpected error: {}", stderr);
+    
+    cleanup(output);
 }
 
 #[test]